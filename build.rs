@@ -0,0 +1,32 @@
+/// exposes the git revs of the engine crates to the code, see `calculate/engines.rs`
+/// so /branches always reports what Cargo.toml actually pins
+
+use std::fs;
+
+const ENGINE_CRATES: [(&str, &str); 3] = [
+    ("refx-pp-rs", "REFX_PP_REVISION"),
+    ("if-servers-legit", "IF_SERVERS_LEGIT_REVISION"),
+    ("live-pp", "LIVE_PP_REVISION"),
+];
+
+fn main() {
+    println!("cargo:rerun-if-changed=Cargo.toml");
+
+    let manifest: toml::Table = fs::read_to_string("Cargo.toml")
+        .expect("Failed to read Cargo.toml")
+        .parse()
+        .expect("Cargo.toml is not valid toml");
+
+    let dependencies = manifest.get("dependencies")
+        .and_then(|dependencies| dependencies.as_table())
+        .expect("Cargo.toml has no [dependencies]");
+
+    for (name, var) in ENGINE_CRATES {
+        let rev = dependencies.get(name)
+            .and_then(|dependency| dependency.get("rev"))
+            .and_then(|rev| rev.as_str())
+            .unwrap_or_else(|| panic!("{} in Cargo.toml has no rev", name));
+
+        println!("cargo:rustc-env={}={}", var, rev);
+    }
+}
@@ -1,30 +1,543 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use futures::future::{BoxFuture, FutureExt, Shared};
 use tokio::fs::{self, create_dir_all};
-use reqwest;
 use thiserror::Error;
 use std::env;
 
+use refx_pp_rs::Beatmap;
+use serde::Serialize;
+
+use crate::calculate::parsed::ParsedBeatmaps;
+use crate::http::{HttpClient, HttpError};
+
 #[derive(Error, Debug)]
 pub enum BeatmapCacheError {
     #[error("IO error: {0}")]
     IOError(#[from] std::io::Error),
     #[error("Request error: {0}")]
     RequestError(#[from] reqwest::Error),
+    #[error("{0}")]
+    Http(#[from] HttpError),
+    #[error("Invalid beatmap {beatmap_id}: {reason}")]
+    InvalidBeatmap {
+        beatmap_id: u64,
+        reason: String,
+    },
+    #[error("beatmap version mismatch for {beatmap_id}: expected md5 {expected}, got {actual}")]
+    VersionMismatch {
+        beatmap_id: u64,
+        expected: String,
+        actual: String,
+    },
+    #[error("Source '{source_name}' timed out")]
+    Timeout {
+        source_name: String,
+    },
+    #[error("No beatmap sources configured")]
+    NoSources,
+    /// the error of a download another caller was waiting on as well
+    #[error("{0}")]
+    Shared(Arc<BeatmapCacheError>),
+}
+
+/// the downloaded file and its md5
+type InFlightDownload = Shared<BoxFuture<'static, Result<(Vec<u8>, String), Arc<BeatmapCacheError>>>>;
+
+// used when a source doesnt set its own timeout
+const DEFAULT_SOURCE_TIMEOUT_SECS: u64 = 10;
+const OFFICIAL_URL: &str = "https://osu.ppy.sh/osu/{id}";
+
+#[derive(Debug, Clone)]
+pub enum SourceKind {
+    /// `{id}` in the url gets replaced with the beatmap id
+    Remote { url: String },
+    /// a directory with `{id}.osu` files in it
+    Local { dir: PathBuf },
+}
+
+#[derive(Debug, Clone)]
+pub struct BeatmapSource {
+    pub name: String,
+    pub kind: SourceKind,
+    pub timeout: Duration,
+}
+
+impl BeatmapSource {
+    /// `official`, `https://mirror/osu/{id}` or `dir:/path/to/maps`,
+    /// optionally followed by `|<timeout in seconds>`
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (spec, timeout) = match spec.rsplit_once('|') {
+            Some((spec, secs)) => {
+                let secs = secs.trim().parse::<u64>()
+                    .map_err(|_| format!("Invalid timeout in beatmap source '{}'", spec))?;
+                (spec.trim(), Duration::from_secs(secs))
+            },
+            None => (spec.trim(), Duration::from_secs(DEFAULT_SOURCE_TIMEOUT_SECS)),
+        };
+
+        let kind = if spec == "official" {
+            SourceKind::Remote { url: OFFICIAL_URL.to_string() }
+        } else if let Some(dir) = spec.strip_prefix("dir:") {
+            SourceKind::Local { dir: PathBuf::from(dir) }
+        } else if spec.starts_with("http://") || spec.starts_with("https://") {
+            if !spec.contains("{id}") {
+                return Err(format!("Beatmap source '{}' is missing an {{id}} placeholder", spec));
+            }
+            SourceKind::Remote { url: spec.to_string() }
+        } else {
+            return Err(format!("Unknown beatmap source '{}'", spec));
+        };
+
+        Ok(Self {
+            name: spec.to_string(),
+            kind,
+            timeout,
+        })
+    }
+
+    /// `BEATMAP_SOURCES` is a comma separated list tried in order, defaults to just `official`
+    pub fn from_env() -> Result<Vec<Self>, String> {
+        let specs = env::var("BEATMAP_SOURCES").unwrap_or_else(|_| "official".to_string());
+
+        specs.split(',')
+            .filter(|spec| !spec.trim().is_empty())
+            .map(Self::parse)
+            .collect()
+    }
+
+    /// the raw bytes, never decoded, the md5 of the score is over the file as it is
+    /// for remote sources `timeout` is per attempt and starts after the rate limiter,
+    /// a burst of downloads waiting on osu.ppy.sh shouldnt time out before it sent anything
+    async fn fetch(&self, http: &HttpClient, beatmap_id: u64) -> Result<Vec<u8>, BeatmapCacheError> {
+        let timed_out = || BeatmapCacheError::Timeout { source_name: self.name.clone() };
+
+        match &self.kind {
+            SourceKind::Remote { url } => {
+                let url = url.replace("{id}", &beatmap_id.to_string());
+                let response = http.get_with_timeout(&url, Some(self.timeout))
+                    .await
+                    .map_err(|e| match e {
+                        HttpError::Request(e) if e.is_timeout() => timed_out(),
+                        e => e.into(),
+                    })?;
+                let expected_len = response.content_length();
+                let beatmap_content = response.bytes()
+                    .await
+                    .map_err(|e| if e.is_timeout() { timed_out() } else { e.into() })?;
+
+                // a connection cut mid-body can still parse, the length doesnt lie
+                if let Some(expected_len) = expected_len {
+                    if beatmap_content.len() as u64 != expected_len {
+                        return Err(BeatmapCacheError::InvalidBeatmap {
+                            beatmap_id,
+                            reason: format!("truncated download, got {} of {} bytes", beatmap_content.len(), expected_len),
+                        });
+                    }
+                }
+
+                Ok(beatmap_content.to_vec())
+            },
+            SourceKind::Local { dir } => {
+                let path = dir.join(format!("{}.osu", beatmap_id));
+                tokio::time::timeout(self.timeout, fs::read(path))
+                    .await
+                    .map_err(|_| timed_out())?
+                    .map_err(Into::into)
+            },
+        }
+    }
+}
+
+fn md5_hex(contents: &[u8]) -> String {
+    format!("{:x}", md5::compute(contents))
+}
+
+/// makes sure we got an actual .osu file and not an error page or half a download
+pub fn validate_beatmap(contents: &[u8]) -> Result<(), String> {
+    // some editors save with a utf-8 bom
+    let header = contents.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(contents);
+    if !header.starts_with(b"osu file format v") {
+        return Err("missing 'osu file format v' header".to_string());
+    }
+
+    let map = Beatmap::from_bytes(contents).map_err(|e| format!("failed to parse: {}", e))?;
+    if map.hit_objects.is_empty() {
+        return Err("no hit objects, the file is probably truncated".to_string());
+    }
+
+    Ok(())
+}
+
+/// `validate_beatmap` parses the whole map, so it runs on the blocking pool and not on a tokio worker
+/// gives the contents back with their md5
+async fn validate_blocking(beatmap_id: u64, contents: Vec<u8>) -> Result<(Vec<u8>, String), BeatmapCacheError> {
+    tokio::task::spawn_blocking(move || {
+        validate_beatmap(&contents)
+            .map_err(|reason| BeatmapCacheError::InvalidBeatmap { beatmap_id, reason })?;
+        let md5 = md5_hex(&contents);
+        Ok((contents, md5))
+    })
+        .await
+        .map_err(std::io::Error::other)?
+}
+
+/// writes into a temp file next to the target and renames it over,
+/// so a crash halfway through never leaves a broken file in the cache
+fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let mut file = tempfile::NamedTempFile::new_in(dir)?;
+    file.write_all(contents)?;
+    file.persist(path).map_err(|e| e.error)?;
+    Ok(())
+}
+
+/// how big the cache directory may get, `None` means unlimited
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheBudget {
+    pub max_bytes: Option<u64>,
+    pub max_files: Option<usize>,
+}
+
+impl CacheBudget {
+    /// `BEATMAP_CACHE_MAX_BYTES` and `BEATMAP_CACHE_MAX_FILES`, unset or 0 for no limit
+    pub fn from_env() -> Self {
+        let limit = |key: &str| env::var(key)
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .filter(|v| *v > 0);
+
+        Self {
+            max_bytes: limit("BEATMAP_CACHE_MAX_BYTES"),
+            max_files: limit("BEATMAP_CACHE_MAX_FILES").map(|v| v as usize),
+        }
+    }
+}
+
+struct IndexEntry {
+    size: u64,
+    last_access: u64,
+    /// only known once the file was read or written since startup
+    md5: Option<String>,
+}
+
+/// what is on disk and when it was last used, so we know what to evict
+#[derive(Default)]
+struct CacheIndex {
+    entries: HashMap<u64, IndexEntry>,
+    // bumped on every access, cheaper and more predictable than timestamps
+    clock: u64,
+    total_bytes: u64,
+    /// beatmap id -> number of `BeatmapPin`s, pinned files are never evicted
+    pins: HashMap<u64, usize>,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+impl CacheIndex {
+    fn touch(&mut self, beatmap_id: u64, size: u64) {
+        self.clock += 1;
+
+        let entry = self.entries.entry(beatmap_id).or_insert(IndexEntry { size: 0, last_access: 0, md5: None });
+        self.total_bytes = self.total_bytes - entry.size + size;
+        entry.size = size;
+        entry.last_access = self.clock;
+    }
+
+    fn set_md5(&mut self, beatmap_id: u64, md5: String) {
+        if let Some(entry) = self.entries.get_mut(&beatmap_id) {
+            entry.md5 = Some(md5);
+        }
+    }
+
+    fn remove(&mut self, beatmap_id: u64) {
+        if let Some(entry) = self.entries.remove(&beatmap_id) {
+            self.total_bytes -= entry.size;
+        }
+    }
+
+    fn over_budget(&self, budget: &CacheBudget) -> bool {
+        budget.max_bytes.is_some_and(|max| self.total_bytes > max)
+            || budget.max_files.is_some_and(|max| self.entries.len() > max)
+    }
+
+    fn pin(&mut self, beatmap_id: u64) {
+        *self.pins.entry(beatmap_id).or_default() += 1;
+    }
+
+    fn unpin(&mut self, beatmap_id: u64) {
+        if let Some(count) = self.pins.get_mut(&beatmap_id) {
+            *count -= 1;
+            if *count == 0 {
+                self.pins.remove(&beatmap_id);
+            }
+        }
+    }
+
+    fn least_recently_used(&self, keep: Option<u64>) -> Option<u64> {
+        self.entries.iter()
+            .filter(|(id, _)| Some(**id) != keep && !self.pins.contains_key(*id))
+            .min_by_key(|(_, entry)| entry.last_access)
+            .map(|(id, _)| *id)
+    }
+}
+
+/// keeps a beatmap from being evicted while a calculation still needs it
+pub struct BeatmapPin {
+    index: Arc<Mutex<CacheIndex>>,
+    beatmap_id: u64,
+}
+
+impl Drop for BeatmapPin {
+    fn drop(&mut self) {
+        self.index.lock().unwrap().unpin(self.beatmap_id);
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct CacheStats {
+    pub files: usize,
+    pub bytes: u64,
+    pub max_files: Option<usize>,
+    pub max_bytes: Option<u64>,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    /// source name -> cached beatmaps it served
+    pub sources: HashMap<String, usize>,
 }
 
 #[derive(Clone)]
 pub struct BeatmapCache {
     cache_dir: PathBuf,
+    sources: Arc<Vec<BeatmapSource>>,
+    http: HttpClient,
+    /// beatmap id -> name of the source that served it,
+    /// kept in a `{id}.source` file next to the map so it survives restarts
+    served_by: Arc<RwLock<HashMap<u64, String>>>,
+    budget: CacheBudget,
+    index: Arc<Mutex<CacheIndex>>,
+    /// parsed maps kept in memory, so popular maps arent parsed over and over
+    parsed: ParsedBeatmaps,
+    /// downloads currently running, keyed by beatmap id,
+    /// so concurrent callers share one download instead of racing each other
+    in_flight: Arc<Mutex<HashMap<u64, InFlightDownload>>>,
 }
 
 impl BeatmapCache {
-    pub fn new() -> Self {
+    pub fn new(
+        sources: Vec<BeatmapSource>,
+        http: HttpClient,
+        budget: CacheBudget,
+        parsed: ParsedBeatmaps,
+    ) -> Self {
         let cache_dir = PathBuf::from(env::var("BEATMAP_PATH").expect(".data/beatmaps"));
-        Self { cache_dir }
+        Self {
+            cache_dir,
+            sources: Arc::new(sources),
+            http,
+            served_by: Arc::new(RwLock::new(HashMap::new())),
+            budget,
+            index: Arc::new(Mutex::new(CacheIndex::default())),
+            parsed,
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn parsed(&self) -> &ParsedBeatmaps {
+        &self.parsed
+    }
+
+    /// md5 of the file on disk, if it has been read or written since startup
+    pub fn cached_md5(&self, beatmap_id: u64) -> Option<String> {
+        self.index.lock().unwrap()
+            .entries
+            .get(&beatmap_id)
+            .and_then(|entry| entry.md5.clone())
+    }
+
+    /// counts a hit that was served from memory without touching the disk,
+    /// so the file doesnt look unused and get evicted
+    pub fn record_hit(&self, beatmap_id: u64) {
+        let mut index = self.index.lock().unwrap();
+        if let Some(size) = index.entries.get(&beatmap_id).map(|entry| entry.size) {
+            index.hits += 1;
+            index.touch(beatmap_id, size);
+        }
+    }
+
+    /// the file wont be evicted until the returned pin is dropped
+    pub fn pin(&self, beatmap_id: u64) -> BeatmapPin {
+        self.index.lock().unwrap().pin(beatmap_id);
+        BeatmapPin {
+            index: self.index.clone(),
+            beatmap_id,
+        }
+    }
+
+    /// the source a cached beatmap came from, unknown for files cached before it was recorded
+    pub fn served_by(&self, beatmap_id: u64) -> Option<String> {
+        self.served_by.read().unwrap().get(&beatmap_id).cloned()
     }
 
     pub async fn ensure_cache_exists(&self) -> Result<(), BeatmapCacheError> {
         create_dir_all(&self.cache_dir).await?;
+        self.load_index().await?;
+        Ok(())
+    }
+
+    /// picks up whatever is already on disk, oldest access first
+    /// so the access order survives restarts as well as the filesystem lets it
+    async fn load_index(&self) -> Result<(), BeatmapCacheError> {
+        let mut files: Vec<(u64, u64, SystemTime)> = Vec::new();
+        let mut dir = fs::read_dir(&self.cache_dir).await?;
+
+        while let Some(entry) = dir.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("osu") {
+                continue;
+            }
+            let Some(beatmap_id) = path.file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.parse::<u64>().ok()) else {
+                continue;
+            };
+
+            let metadata = entry.metadata().await?;
+            let accessed = metadata.accessed()
+                .or_else(|_| metadata.modified())
+                .unwrap_or(SystemTime::UNIX_EPOCH);
+            files.push((beatmap_id, metadata.len(), accessed));
+        }
+
+        files.sort_by_key(|(_, _, accessed)| *accessed);
+
+        let mut served_by = HashMap::new();
+        for (beatmap_id, _, _) in &files {
+            if let Ok(source) = fs::read_to_string(self.get_source_path(*beatmap_id)).await {
+                served_by.insert(*beatmap_id, source.trim().to_string());
+            }
+        }
+        *self.served_by.write().unwrap() = served_by;
+
+        {
+            let mut index = self.index.lock().unwrap();
+            for (beatmap_id, size, _) in &files {
+                index.touch(*beatmap_id, *size);
+            }
+            println!("Beatmap cache has {} files ({} bytes).", index.entries.len(), index.total_bytes);
+        }
+
+        self.evict(None).await;
+        Ok(())
+    }
+
+    /// removes least recently used files until the cache fits the budget again
+    /// pinned files and `keep` are skipped, the files are deleted on the blocking pool
+    async fn evict(&self, keep: Option<u64>) {
+        let evicted: Vec<(u64, PathBuf, PathBuf)> = {
+            let mut index = self.index.lock().unwrap();
+            let mut evicted = Vec::new();
+
+            while index.over_budget(&self.budget) {
+                let Some(beatmap_id) = index.least_recently_used(keep) else {
+                    break;
+                };
+
+                index.remove(beatmap_id);
+                index.evictions += 1;
+                self.served_by.write().unwrap().remove(&beatmap_id);
+                evicted.push((beatmap_id, self.get_beatmap_path(beatmap_id), self.get_source_path(beatmap_id)));
+            }
+
+            evicted
+        };
+
+        if evicted.is_empty() {
+            return;
+        }
+
+        let index = self.index.clone();
+        let deleted = tokio::task::spawn_blocking(move || {
+            for (beatmap_id, path, source_path) in evicted {
+                // it might have been pinned and downloaded again in the meantime
+                let index = index.lock().unwrap();
+                if index.entries.contains_key(&beatmap_id) || index.pins.contains_key(&beatmap_id) {
+                    continue;
+                }
+
+                // older files dont have a .source next to them
+                if let Err(e) = std::fs::remove_file(&source_path) {
+                    if e.kind() != std::io::ErrorKind::NotFound {
+                        eprintln!("Failed to remove source of beatmap {}: {}", beatmap_id, e);
+                    }
+                }
+
+                if let Err(e) = std::fs::remove_file(&path) {
+                    eprintln!("Failed to evict beatmap {}: {}", beatmap_id, e);
+                } else {
+                    println!("Evicted beatmap {} from cache.", beatmap_id);
+                }
+            }
+        }).await;
+
+        if let Err(e) = deleted {
+            eprintln!("Failed to evict beatmaps: {}", e);
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let mut sources: HashMap<String, usize> = HashMap::new();
+        for source in self.served_by.read().unwrap().values() {
+            *sources.entry(source.clone()).or_default() += 1;
+        }
+
+        let index = self.index.lock().unwrap();
+        CacheStats {
+            files: index.entries.len(),
+            bytes: index.total_bytes,
+            max_files: self.budget.max_files,
+            max_bytes: self.budget.max_bytes,
+            hits: index.hits,
+            misses: index.misses,
+            evictions: index.evictions,
+            sources,
+        }
+    }
+
+    /// writes a beatmap and where it came from into the cache and makes room for it if needed
+    async fn store_beatmap(&self, beatmap_id: u64, beatmap_content: &[u8], source: &str) -> Result<(), BeatmapCacheError> {
+        let beatmap_path = self.get_beatmap_path(beatmap_id);
+
+        if let Some(parent) = beatmap_path.parent() {
+            create_dir_all(parent).await?;
+        }
+
+        let path = beatmap_path.clone();
+        let source_path = self.get_source_path(beatmap_id);
+        let contents = beatmap_content.to_vec();
+        let source_name = source.to_string();
+        let md5 = tokio::task::spawn_blocking(move || {
+            write_atomic(&path, &contents)?;
+            write_atomic(&source_path, source_name.as_bytes())?;
+            Ok::<_, std::io::Error>(md5_hex(&contents))
+        })
+            .await
+            .map_err(std::io::Error::other)??;
+
+        self.served_by.write().unwrap().insert(beatmap_id, source.to_string());
+
+        {
+            let mut index = self.index.lock().unwrap();
+            index.touch(beatmap_id, beatmap_content.len() as u64);
+            index.set_md5(beatmap_id, md5);
+        }
+        self.evict(Some(beatmap_id)).await;
+
         Ok(())
     }
 
@@ -32,27 +545,272 @@ impl BeatmapCache {
         self.cache_dir.join(format!("{}.osu", beatmap_id))
     }
 
-    pub async fn get_or_download_beatmap(&self, beatmap_id: u64) -> Result<String, BeatmapCacheError> {
+    fn get_source_path(&self, beatmap_id: u64) -> PathBuf {
+        self.cache_dir.join(format!("{}.source", beatmap_id))
+    }
+
+    /// seeds the cache from a local file instead of a source, see `import.rs`
+    pub async fn import_beatmap(&self, beatmap_id: u64, beatmap_content: &[u8]) -> Result<(), BeatmapCacheError> {
+        let (beatmap_content, _) = validate_blocking(beatmap_id, beatmap_content.to_vec()).await?;
+
+        self.store_beatmap(beatmap_id, &beatmap_content, "import").await?;
+
+        Ok(())
+    }
+
+    /// `md5` is the hash the score was set on, if the cached file doesnt match
+    /// it gets downloaded again, in case the map was updated since
+    /// the file is returned as raw bytes, it is only decoded when parsed
+    pub async fn get_or_download_beatmap(&self, beatmap_id: u64, md5: Option<&str>) -> Result<Vec<u8>, BeatmapCacheError> {
         let beatmap_path = self.get_beatmap_path(beatmap_id);
+        let md5 = md5.filter(|md5| !md5.is_empty());
 
         if beatmap_path.exists() {
-            return Ok(fs::read_to_string(&beatmap_path).await?);
+            let beatmap_content = fs::read(&beatmap_path).await?;
+            let actual = md5_hex(&beatmap_content);
+
+            match md5 {
+                Some(expected) if actual != expected => {
+                    println!("Cached beatmap {} does not match md5 {}, downloading again.", beatmap_id, expected);
+                },
+                _ => {
+                    let mut index = self.index.lock().unwrap();
+                    index.hits += 1;
+                    index.touch(beatmap_id, beatmap_content.len() as u64);
+                    index.set_md5(beatmap_id, actual);
+                    return Ok(beatmap_content);
+                },
+            }
         }
 
-        let url = format!("https://osu.ppy.sh/osu/{}", beatmap_id);
-        let client = reqwest::Client::new();
-        let beatmap_content = client.get(&url)
-            .send()
-            .await?
-            .text()
-            .await?;
+        self.index.lock().unwrap().misses += 1;
 
-        if let Some(parent) = beatmap_path.parent() {
-            create_dir_all(parent).await?;
+        let download = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+
+            match in_flight.get(&beatmap_id) {
+                Some(download) => {
+                    println!("Beatmap {} is already being downloaded, waiting for it.", beatmap_id);
+                    download.clone()
+                },
+                None => {
+                    let cache = self.clone();
+                    let wanted = md5.map(str::to_string);
+
+                    let download = async move {
+                        let result = cache.download_beatmap(beatmap_id, wanted.as_deref())
+                            .await
+                            .map_err(Arc::new);
+                        cache.in_flight.lock().unwrap().remove(&beatmap_id);
+                        result
+                    }
+                        .boxed()
+                        .shared();
+
+                    in_flight.insert(beatmap_id, download.clone());
+                    download
+                },
+            }
+        };
+
+        // the download may have been started for another md5, so every caller checks its own
+        let (beatmap_content, actual) = download.await.map_err(BeatmapCacheError::Shared)?;
+
+        match md5 {
+            Some(expected) if actual != expected => Err(BeatmapCacheError::VersionMismatch {
+                beatmap_id,
+                expected: expected.to_string(),
+                actual,
+            }),
+            _ => Ok(beatmap_content),
+        }
+    }
+
+    /// tries every source in order until one has `md5`, only ever called through the in-flight map
+    /// if none of them do, the first valid file is kept, it is still the newest version there is
+    async fn download_beatmap(&self, beatmap_id: u64, md5: Option<&str>) -> Result<(Vec<u8>, String), BeatmapCacheError> {
+        let mut last_error = BeatmapCacheError::NoSources;
+        let mut fallback = None;
+
+        for source in self.sources.iter() {
+            match self.fetch_from(source, beatmap_id).await {
+                Ok((beatmap_content, actual)) if md5.is_none_or(|expected| expected == actual) => {
+                    println!("Beatmap {} served by '{}'.", beatmap_id, source.name);
+                    self.store_beatmap(beatmap_id, &beatmap_content, &source.name).await?;
+
+                    return Ok((beatmap_content, actual));
+                },
+                Ok((beatmap_content, actual)) => {
+                    println!("Source '{}' has a different version of beatmap {} (md5 {}).", source.name, beatmap_id, actual);
+                    fallback.get_or_insert((beatmap_content, actual, &source.name));
+                },
+                Err(e) => {
+                    println!("Source '{}' failed for beatmap {}: {}", source.name, beatmap_id, e);
+                    last_error = e;
+                },
+            }
         }
 
-        fs::write(&beatmap_path, &beatmap_content).await?;
+        let Some((beatmap_content, actual, source_name)) = fallback else {
+            return Err(last_error);
+        };
+
+        println!("Beatmap {} served by '{}'.", beatmap_id, source_name);
+        self.store_beatmap(beatmap_id, &beatmap_content, source_name).await?;
+
+        Ok((beatmap_content, actual))
+    }
+
+    /// one attempt at one source, the result is only good if it is a valid map
+    /// gives the contents back with their md5
+    async fn fetch_from(&self, source: &BeatmapSource, beatmap_id: u64) -> Result<(Vec<u8>, String), BeatmapCacheError> {
+        let beatmap_content = source.fetch(&self.http, beatmap_id).await?;
+        validate_blocking(beatmap_id, beatmap_content).await
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::http::HttpConfig;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const FIXTURE: &[u8] = include_bytes!("calculate/fixtures/std.osu");
+
+    fn cache_in(dir: &Path, sources: Vec<BeatmapSource>) -> BeatmapCache {
+        BeatmapCache {
+            cache_dir: dir.to_path_buf(),
+            sources: Arc::new(sources),
+            http: HttpClient::new(HttpConfig::from_env().unwrap()).unwrap(),
+            served_by: Arc::new(RwLock::new(HashMap::new())),
+            budget: CacheBudget::default(),
+            index: Arc::new(Mutex::new(CacheIndex::default())),
+            parsed: ParsedBeatmaps::from_env(),
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// answers every request with `body` after a short delay, returns the url and a request counter
+    async fn serve(body: &'static [u8]) -> (String, Arc<AtomicUsize>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/osu/{{id}}", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+
+                tokio::spawn(async move {
+                    let mut request = [0; 1024];
+                    let _ = socket.read(&mut request).await;
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+
+                    let header = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len());
+                    let _ = socket.write_all(header.as_bytes()).await;
+                    let _ = socket.write_all(body).await;
+                });
+            }
+        });
+
+        (url, requests)
+    }
+
+    #[tokio::test]
+    async fn concurrent_callers_share_one_download() {
+        let (url, requests) = serve(FIXTURE).await;
+        let dir = tempfile::tempdir().unwrap();
+        let cache = cache_in(dir.path(), vec![BeatmapSource::parse(&url).unwrap()]);
+        let md5 = md5_hex(FIXTURE);
+
+        let (first, second, third) = tokio::join!(
+            cache.get_or_download_beatmap(1, None),
+            cache.get_or_download_beatmap(1, Some(&md5)),
+            cache.get_or_download_beatmap(1, Some("00000000000000000000000000000000")),
+        );
+
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+        assert_eq!(first.unwrap(), FIXTURE);
+        assert_eq!(second.unwrap(), FIXTURE);
+        // the shared file is checked against each caller's own md5
+        assert!(matches!(third, Err(BeatmapCacheError::VersionMismatch { .. })));
+        assert!(cache.in_flight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn outdated_cached_files_are_downloaded_again() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        std::fs::write(source_dir.path().join("1.osu"), FIXTURE).unwrap();
+        std::fs::write(cache_dir.path().join("1.osu"), b"stale").unwrap();
+
+        let source = BeatmapSource::parse(&format!("dir:{}", source_dir.path().display())).unwrap();
+        let cache = cache_in(cache_dir.path(), vec![source]);
+
+        let beatmap = cache.get_or_download_beatmap(1, Some(&md5_hex(FIXTURE))).await.unwrap();
+        assert_eq!(beatmap, FIXTURE);
+        assert_eq!(std::fs::read(cache_dir.path().join("1.osu")).unwrap(), FIXTURE);
+
+        // no source has the version the score was set on
+        let error = cache.get_or_download_beatmap(1, Some("00000000000000000000000000000000")).await.unwrap_err();
+        assert_eq!(crate::error::CalcError::from(error).code(), "beatmap_version_mismatch");
+        assert_eq!(cache.stats().misses, 2);
+    }
+
+    #[test]
+    fn evicts_least_recently_used_first() {
+        let mut index = CacheIndex::default();
+        index.touch(1, 100);
+        index.touch(2, 200);
+        index.touch(3, 300);
+        index.touch(1, 100);
+
+        assert_eq!(index.least_recently_used(None), Some(2));
+        // the file that was just stored is never its own victim
+        assert_eq!(index.least_recently_used(Some(2)), Some(3));
 
-        Ok(beatmap_content)
+        index.remove(2);
+        assert_eq!(index.least_recently_used(None), Some(3));
+        assert_eq!(index.total_bytes, 400);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn pinned_files_are_skipped() {
+        let mut index = CacheIndex::default();
+        index.touch(1, 100);
+        index.touch(2, 100);
+
+        index.pin(1);
+        index.pin(1);
+        assert_eq!(index.least_recently_used(None), Some(2));
+        assert_eq!(index.least_recently_used(Some(2)), None);
+
+        index.unpin(1);
+        assert_eq!(index.least_recently_used(Some(2)), None);
+        index.unpin(1);
+        assert_eq!(index.least_recently_used(Some(2)), Some(1));
+    }
+
+    #[test]
+    fn retouching_updates_the_size() {
+        let mut index = CacheIndex::default();
+        index.touch(1, 100);
+        index.touch(1, 250);
+
+        assert_eq!(index.total_bytes, 250);
+        assert_eq!(index.entries.len(), 1);
+    }
+
+    #[test]
+    fn checks_both_budgets() {
+        let mut index = CacheIndex::default();
+        index.touch(1, 100);
+        index.touch(2, 100);
+
+        assert!(!index.over_budget(&CacheBudget::default()));
+        assert!(!index.over_budget(&CacheBudget { max_bytes: Some(200), max_files: Some(2) }));
+        assert!(index.over_budget(&CacheBudget { max_bytes: Some(199), max_files: None }));
+        assert!(index.over_budget(&CacheBudget { max_bytes: None, max_files: Some(1) }));
+    }
+}
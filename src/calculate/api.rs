@@ -4,171 +4,655 @@
 /// WHATS fun with this\
 /// will be used for the server in the future if i care
 
-use std::error::Error;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
 
-use reqwest;
+use futures::channel::mpsc::{self, UnboundedReceiver};
 use futures::future::join_all;
+use futures::stream::{FuturesUnordered, Stream, StreamExt};
+use tokio::task::{JoinError, JoinHandle};
 
 use crate::models::{
+    BatchScore,
+    BatchScoreResult,
+    BeatmapInfo,
+    ComparisonResults,
+    Failure,
+    LeaderboardEntry,
     LeaderboardResponse, 
+    LeaderboardResults,
+    LeaderboardStanding,
+    PlayerResults,
     PlayerScore, 
     PPCalculationResult, 
-    ScoresResponse
+    ScoreComparison,
+    ScoresResponse,
+    StreamEvent,
+    UploadScoreParams,
 };
-use crate::beatmap::BeatmapCache;
-use super::cache::Cache;
+use crate::beatmap::{validate_beatmap, BeatmapCache};
+use crate::http::HttpClient;
+use crate::error::CalcError;
+use super::cache::{Cache, CacheKind};
 use crate::calculate::calculate;
+use crate::calculate::pool;
+use crate::calculate::parsed::MapSource;
 use crate::calculate::calculate::PPCalculationType;
+use crate::calculate::engine::registry;
+use crate::calculate::params::{GameMode, Mode, NamedParam, Version};
+use crate::calculate::utils::{hitresults_from_acc, round};
+
+// bancho.py refuses anything above 100 per request
+const PAGE_SIZE: usize = 100;
+
+/// what a leaderboard calculation reaches out to, shared by every request
+#[derive(Clone)]
+pub struct CalcContext {
+    pub http: HttpClient,
+    /// upstream api responses
+    pub cache: Cache,
+    pub beatmap_cache: BeatmapCache,
+}
+
+/// how much of the leaderboard to recalculate
+#[derive(Debug, Clone, Copy)]
+pub struct LeaderboardOptions {
+    pub offset: usize,
+    pub limit: usize,
+    /// best scores per player, at most 100
+    pub score_limit: usize,
+    /// skips the response cache and overwrites it with what upstream sends now
+    pub fresh: bool,
+}
+
+impl Default for LeaderboardOptions {
+    fn default() -> Self {
+        Self {
+            offset: 0,
+            limit: 10,
+            score_limit: 10,
+            fresh: false,
+        }
+    }
+}
 
 // this shouldnt be used if it used for the server
-async fn fetch_leaderboard(mode: u8, cache: &Cache) -> Result<LeaderboardResponse, Box<dyn Error>> {
+async fn fetch_leaderboard_page(
+    http: &HttpClient,
+    mode: Mode,
+    offset: usize,
+    limit: usize,
+    fresh: bool,
+    cache: &Cache,
+) -> Result<LeaderboardResponse, CalcError> {
     let url = format!(
-        "https://api.{}/v1/get_leaderboard?mode={}&limit=10",
-        env::var("URL").expect(""), mode
+        "https://api.{}/v1/get_leaderboard?mode={}&limit={}&offset={}",
+        env::var("URL").expect(""), mode.number(), limit, offset
     );
     println!("Fetching leaderboard from mode {}, {}", mode, url);
 
-    if let Some(cached_response) = cache.get(&url) {
+    if let Some(cached_response) = cache.get(&url).filter(|_| !fresh) {
         println!("Returning cached leaderboard data.");
         let leaderboard: LeaderboardResponse = serde_json::from_str(&cached_response)?;
         return Ok(leaderboard);
     }
 
-    let client = reqwest::Client::new();
-    let leaderboard = client.get(&url)
-        .send()
+    let leaderboard = http.get(&url)
         .await?
         .json::<LeaderboardResponse>()
         .await?;
 
-    cache.set(&url, serde_json::to_string(&leaderboard)?);
+    cache.set(CacheKind::Leaderboard, &url, serde_json::to_string(&leaderboard)?);
 
     println!("Fetched leaderboard successfully.");
     Ok(leaderboard)
 }
 
-async fn fetch_player_scores(player_id: u64, mode: u8, cache: &Cache) -> Result<Vec<PlayerScore>, Box<dyn Error>> {
+/// pages through the leaderboard until `options.limit` entries are fetched
+/// or the leaderboard runs out
+async fn fetch_leaderboard(
+    http: &HttpClient,
+    mode: Mode,
+    options: LeaderboardOptions,
+    cache: &Cache,
+) -> Result<Vec<LeaderboardEntry>, CalcError> {
+    let mut entries: Vec<LeaderboardEntry> = Vec::with_capacity(options.limit);
+
+    while entries.len() < options.limit {
+        let limit = (options.limit - entries.len()).min(PAGE_SIZE);
+        let page = fetch_leaderboard_page(http, mode, options.offset + entries.len(), limit, options.fresh, cache).await?;
+        let fetched = page.leaderboard.len();
+
+        entries.extend(page.leaderboard);
+
+        if fetched < limit {
+            break;
+        }
+    }
+
+    Ok(entries)
+}
+
+async fn fetch_player_scores(
+    http: &HttpClient,
+    player_id: u64,
+    mode: Mode,
+    limit: usize,
+    fresh: bool,
+    cache: &Cache,
+) -> Result<Vec<PlayerScore>, CalcError> {
     let url = format!(
-        "https://api.{}/v1/get_player_scores?id={}&mode={}&scope=best&limit=10",
-        env::var("URL").expect(""), player_id, mode
+        "https://api.{}/v1/get_player_scores?id={}&mode={}&scope=best&limit={}",
+        env::var("URL").expect(""), player_id, mode.number(), limit
     );
     println!("Fetching scores for player {} in mode {} from {}", player_id, mode, url);
 
-    if let Some(cached_response) = cache.get(&url) {
+    if let Some(cached_response) = cache.get(&url).filter(|_| !fresh) {
         println!("Returning cached scores for player {}", player_id);
         let scores_response: ScoresResponse = serde_json::from_str(&cached_response)?;
         return Ok(scores_response.scores);
     }
 
-    let client = reqwest::Client::new();
-    let player_scores = client.get(&url)
-        .send()
+    let player_scores = http.get(&url)
         .await?
         .json::<ScoresResponse>()
         .await?;
 
-    cache.set(&url, serde_json::to_string(&player_scores)?);
+    cache.set(CacheKind::PlayerScores, &url, serde_json::to_string(&player_scores)?);
 
     println!("Fetched {} scores for player {}", player_scores.scores.len(), player_id);
     Ok(player_scores.scores)
 }
 
-pub async fn calculate_pp_now(
-    mode: u8, 
-    beatmap_cache: &BeatmapCache, 
-    version: u8,
-    rx: bool,
-    branch: u8,
-) -> Result<HashMap<String, Vec<PPCalculationResult>>, Box<dyn Error>> {
-    println!("Calculating PP for leaderboard in mode {}", mode);
+/// recalculates a single score, downloading the beatmap first if it isnt cached (or outdated)
+pub async fn calculate_score(
+    beatmap_cache: &BeatmapCache,
+    score: &PlayerScore,
+    player_name: &str,
+    calc_type: PPCalculationType,
+) -> Result<PPCalculationResult, CalcError> {
+    // only touches the disk (or downloads) if the map isnt parsed in memory yet,
+    // the md5 makes sure it is the version the score was set on either way
+    let mut beatmap = MapSource::cached(beatmap_cache, score.beatmap.id, &score.beatmap.md5);
+    beatmap.prepare(calc_type.engine.map_kind()).await?;
+
+    let score = score.clone();
+    let player_name = player_name.to_string();
+    pool::run(move || calculate::calculate_pp(&beatmap, &score, &player_name, calc_type)).await
+}
+
+/// star rating of a map with `mods`, downloading it first like `calculate_score`
+pub async fn calculate_beatmap_difficulty(
+    beatmap_cache: &BeatmapCache,
+    beatmap_id: u64,
+    md5: &str,
+    mods: u32,
+    calc_type: PPCalculationType,
+) -> Result<f64, CalcError> {
+    let mut beatmap = MapSource::cached(beatmap_cache, beatmap_id, md5);
+    beatmap.prepare(calc_type.engine.map_kind()).await?;
+
+    pool::run(move || calculate::calculate_difficulty(&beatmap, mods, calc_type)).await
+}
+
+/// recalculates an arbitrary list of scores, results come back in the same order
+/// a score that fails gets an error entry instead of failing the whole batch
+pub async fn calculate_batch(
+    beatmap_cache: &BeatmapCache,
+    scores: Vec<BatchScore>,
+    calc_type: PPCalculationType,
+) -> Vec<BatchScoreResult> {
+    println!("Calculating PP for a batch of {} scores", scores.len());
+
+    let tasks = scores.into_iter().map(|entry| async move {
+        let player_name = match (&entry.player_name, entry.player_id) {
+            (Some(name), _) => name.clone(),
+            (None, Some(id)) => id.to_string(),
+            (None, None) => "unknown".to_string(),
+        };
+
+        let (result, error) = match calculate_score(beatmap_cache, &entry.score, &player_name, calc_type).await {
+            Ok(result) => (Some(result), None),
+            Err(e) => {
+                println!("Failed to calculate beatmap {} for '{}': {}", entry.score.beatmap.id, player_name, e);
+                (None, Some(e.info()))
+            },
+        };
+
+        BatchScoreResult {
+            player_name: entry.player_name,
+            player_id: entry.player_id,
+            result,
+            error,
+        }
+    });
+
+    let results = join_all(tasks).await;
+
+    println!("Finished calculating PP for batch.");
+    results
+}
 
+/// calculates an uploaded (possibly unsubmitted) map on every branch and version
+/// the map is only parsed in memory so it never ends up in the id-keyed beatmap cache
+pub async fn calculate_uploaded_beatmap(
+    contents: &[u8],
+    params: UploadScoreParams,
+) -> Result<HashMap<String, Vec<PPCalculationResult>>, CalcError> {
+    // a full parse, kept off the async runtime like the calculations
+    let contents = contents.to_vec();
+    let contents = pool::run(move || {
+        validate_beatmap(&contents)
+            .map_err(|reason| CalcError::InvalidInput(format!("Uploaded file is not a valid .osu file: {}", reason)))?;
+        Ok(contents)
+    }).await?;
+
+    let beatmap = Arc::new(MapSource::uploaded(contents));
+
+    // every engine parses the same hit objects, any of them will do
+    let engine = registry().all().next()
+        .ok_or_else(|| CalcError::Engine("No engines registered".to_string()))?;
+    let map = beatmap.clone();
+    let info = pool::run(move || engine.parse(&map)).await?;
+
+    let (n300, n100, n50, ngeki, nkatu) = match params.hits {
+        Some(hits) => (hits.n300, hits.n100, hits.n50, hits.ngeki, hits.nkatu),
+        None if info.mode == GameMode::Osu => {
+            let (n300, n100, n50) = hitresults_from_acc(info.hit_objects, params.acc, params.misses);
+            (n300, n100, n50, 0, 0)
+        },
+        None => return Err(CalcError::InvalidInput(format!(
+            "Uploaded map is a {} map, send its n300, n100, n50, ngeki and nkatu along.", info.mode
+        ))),
+    };
+
+    let score = PlayerScore {
+        score: 0,
+        pp: 0.0,
+        acc: params.acc,
+        max_combo: params.combo,
+        mods: params.mods,
+        n300,
+        n100,
+        n50,
+        nmiss: params.misses,
+        ngeki,
+        nkatu,
+
+        aim_value: 0,
+        ar_value: 0.0,
+        cs: 0,
+        twval: 0.0,
+        hdr: 0,
+
+        beatmap: BeatmapInfo {
+            id: 0,
+            md5: String::new(),
+        },
+    };
+
+    let mut tasks = Vec::new();
+    for engine in registry().all() {
+        // relax and scorev2 are std only, the other modes just get vanilla
+        for version in Version::ALL.iter().filter(|version| info.mode.check_version(**version).is_ok()) {
+            let calc_type = PPCalculationType::new(engine, info.mode, *version, params.rx);
+
+            let beatmap = beatmap.clone();
+            let score = score.clone();
+            tasks.push(async move {
+                let result = pool::run(move || calculate::calculate_pp(&beatmap, &score, "upload", calc_type)).await?;
+                Ok::<_, CalcError>((engine.name(), result))
+            });
+        }
+    }
+
+    // join_all keeps the order, so every branch gets its versions in order
     let mut pp_results: HashMap<String, Vec<PPCalculationResult>> = HashMap::new();
-    let cache = Cache::new(60);
+    for result in join_all(tasks).await {
+        let (branch_name, result) = result?;
+        pp_results.entry(branch_name.to_string()).or_default().push(result);
+    }
 
-    println!("Fetching global leaderboard...");
-    let leaderboard = fetch_leaderboard(mode, &cache).await?;
-    println!("Fetched leaderboard with {} entries.", leaderboard.leaderboard.len());
+    Ok(pp_results)
+}
+
+/// aborts the task once dropped, so a cancelled job or a closed request
+/// stops everything it spawned instead of leaving it running in the background
+struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> Future for AbortOnDrop<T> {
+    type Output = Result<T, JoinError>;
 
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx)
+    }
+}
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+type PlayerFetch = AbortOnDrop<Result<Vec<PlayerScore>, CalcError>>;
+
+/// spawns one fetch per leaderboard entry so every player's scores load concurrently
+/// the name is kept next to the handle, a panicked task doesnt give it back
+fn spawn_player_fetches(
+    http: &HttpClient,
+    leaderboard: Vec<LeaderboardEntry>,
+    mode: Mode,
+    options: LeaderboardOptions,
+    cache: &Cache,
+) -> Vec<(String, PlayerFetch)> {
     let mut tasks = vec![];
 
-    for entry in leaderboard.leaderboard {
+    for entry in leaderboard {
         let player_id = entry.player_id;
         let player_name = entry.name.clone();
+        let task_player_name = entry.name.clone();
         let mode = mode;
+        let score_limit = options.score_limit;
         let cache = cache.clone();
+        let http = http.clone();
 
         let player_task = tokio::spawn(async move {
-            let scores = fetch_player_scores(player_id, mode, &cache).await.unwrap_or_default();
-            (player_name, scores)
+            let scores = fetch_player_scores(&http, player_id, mode, score_limit, options.fresh, &cache).await;
+            match &scores {
+                Ok(scores) => println!("Fetched {} scores for player '{}'", scores.len(), task_player_name),
+                Err(e) => println!("Failed to fetch scores for player '{}': {}", task_player_name, e),
+            }
+            scores
         });
 
-        tasks.push(player_task);
+        tasks.push((player_name, AbortOnDrop(player_task)));
     }
 
-    let player_results = join_all(tasks).await;
+    tasks
+}
 
-    for result in player_results {
-        let (player_name, scores) = result.unwrap();
-        println!("Fetched {} scores for player '{}'", scores.len(), player_name);
+/// fetches the leaderboard and every player's best scores concurrently
+/// also returns where every player stands, ranks start at `offset + 1`
+async fn fetch_leaderboard_scores(
+    http: &HttpClient,
+    mode: Mode,
+    options: LeaderboardOptions,
+    cache: &Cache,
+) -> Result<(Vec<(String, Result<Vec<PlayerScore>, CalcError>)>, HashMap<String, LeaderboardStanding>), CalcError> {
+    println!("Fetching global leaderboard...");
+    let leaderboard = fetch_leaderboard(http, mode, options, cache).await?;
+    println!("Fetched leaderboard with {} entries.", leaderboard.len());
 
-        let mut results: Vec<PPCalculationResult> = Vec::new();
+    let standings = leaderboard.iter()
+        .enumerate()
+        .map(|(i, entry)| (entry.name.clone(), LeaderboardStanding { pp: entry.pp, rank: options.offset + i + 1 }))
+        .collect();
 
-        for score in scores {
-            let beatmap_path = beatmap_cache.get_beatmap_path(score.beatmap.id);
+    let tasks = spawn_player_fetches(http, leaderboard, mode, options, cache);
+    let players = join_all(tasks.into_iter().map(|(player_name, fetch)| async move {
+        (player_name, join_player_fetch(fetch).await)
+    })).await;
 
-            if !beatmap_path.exists() {
-                println!("Beatmap {} not cached. Downloading...", score.beatmap.id);
-                beatmap_cache.get_or_download_beatmap(score.beatmap.id).await?;
-                println!("Downloaded and cached beatmap {}.", score.beatmap.id);
-            } else {
-                println!("Beatmap {} found in cache.", score.beatmap.id);
-            }
+    Ok((players, standings))
+}
+
+/// a fetch that panicked or got cancelled fails just that player
+async fn join_player_fetch(fetch: PlayerFetch) -> Result<Vec<PlayerScore>, CalcError> {
+    fetch.await.unwrap_or_else(|e| Err(CalcError::Internal(format!("Fetching scores failed: {}", e))))
+}
 
-            // LMFAOO IM CRYING
-            // &version    0 = vn
-            //             1 = rx
-            //             2 = sv2
-            //
-            // &branch     0 = live pp
-            //             1 = main with cv
-            //             2 = main without cv
-            //             3 = if-servers-legit 
-            let pp_result = match branch {
-                0 => match version {
-                    0 => calculate::calculate_pp(beatmap_path.to_str().unwrap(), &score, &player_name, PPCalculationType::VanillaCheatsLive).await?,
-                    1 => calculate::calculate_pp(beatmap_path.to_str().unwrap(), &score, &player_name, PPCalculationType::RelaxCheatsLive).await?,
-                    2 => calculate::calculate_pp(beatmap_path.to_str().unwrap(), &score, &player_name, PPCalculationType::ScoreV2CheatsLive { relax: rx }).await?,
-                    _ => return Err("Invalid version!".into()),
-                },
-                1 => match version {
-                    0 => calculate::calculate_pp(beatmap_path.to_str().unwrap(), &score, &player_name, PPCalculationType::VanillaCheats).await?,
-                    1 => calculate::calculate_pp(beatmap_path.to_str().unwrap(), &score, &player_name, PPCalculationType::RelaxCheats).await?,
-                    2 => calculate::calculate_pp(beatmap_path.to_str().unwrap(), &score, &player_name, PPCalculationType::ScoreV2Cheats { relax: rx }).await?,
-                    _ => return Err("Invalid version!".into()),
-                },
-                2 => match version {
-                    0 => calculate::calculate_pp(beatmap_path.to_str().unwrap(), &score, &player_name, PPCalculationType::VanillaNoCV).await?,
-                    1 => calculate::calculate_pp(beatmap_path.to_str().unwrap(), &score, &player_name, PPCalculationType::RelaxNoCV).await?,
-                    2 => calculate::calculate_pp(beatmap_path.to_str().unwrap(), &score, &player_name, PPCalculationType::ScoreV2NoCV { relax: rx }).await?,
-                    _ => return Err("Invalid version!".into()),
-                },
-                3 => match version {
-                    0 => calculate::calculate_pp(beatmap_path.to_str().unwrap(), &score, &player_name, PPCalculationType::VanillaLegit).await?,
-                    1 => calculate::calculate_pp(beatmap_path.to_str().unwrap(), &score, &player_name, PPCalculationType::RelaxLegit).await?,
-                    2 => calculate::calculate_pp(beatmap_path.to_str().unwrap(), &score, &player_name, PPCalculationType::ScoreV2Legit { relax: rx }).await?,
-                    _ => return Err("Invalid version!".into()),
-                },
-                _ => return Err("Invalid branch!".into()),
-            };
-            
-            results.push(pp_result);
+fn score_failure(player_name: &str, score: &PlayerScore, error: &CalcError) -> Failure {
+    println!("Failed to calculate beatmap {} for '{}': {}", score.beatmap.id, player_name, error);
+    Failure {
+        player_name: Some(player_name.to_string()),
+        beatmap_id: Some(score.beatmap.id),
+        mods: Some(score.mods),
+        error: error.info(),
+    }
+}
+
+fn player_failure(player_name: &str, error: &CalcError) -> Failure {
+    Failure {
+        player_name: Some(player_name.to_string()),
+        beatmap_id: None,
+        mods: None,
+        error: error.info(),
+    }
+}
+
+/// recalculates every score of one player concurrently, results stay in the order they were fetched
+/// scores that fail are reported next to the results instead of failing the player
+async fn calculate_player_scores(
+    beatmap_cache: &BeatmapCache,
+    player_name: &str,
+    scores: &[PlayerScore],
+    calc_type: PPCalculationType,
+) -> (Vec<PPCalculationResult>, Vec<Failure>) {
+    let tasks = scores.iter()
+        .map(|score| calculate_score(beatmap_cache, score, player_name, calc_type));
+
+    let mut results = Vec::with_capacity(scores.len());
+    let mut failures = Vec::new();
+
+    for (score, result) in scores.iter().zip(join_all(tasks).await) {
+        match result {
+            Ok(result) => results.push(result),
+            Err(e) => failures.push(score_failure(player_name, score, &e)),
         }
+    }
 
-        pp_results.insert(player_name, results);
+    (results, failures)
+}
+
+pub async fn calculate_pp_now(
+    ctx: &CalcContext,
+    mode: Mode,
+    calc_type: PPCalculationType,
+    options: LeaderboardOptions,
+) -> Result<LeaderboardResults, CalcError> {
+    println!("Calculating PP for leaderboard in mode {}", mode);
+
+    let (players, standings) = fetch_leaderboard_scores(&ctx.http, mode, options, &ctx.cache).await?;
+    let beatmap_cache = &ctx.beatmap_cache;
+
+    let mut pp_results = LeaderboardResults {
+        standings,
+        ..Default::default()
+    };
+
+    let tasks = players.iter().map(|(player_name, scores)| async move {
+        match scores {
+            Ok(scores) => calculate_player_scores(beatmap_cache, player_name, scores, calc_type).await,
+            Err(e) => (Vec::new(), vec![player_failure(player_name, e)]),
+        }
+    });
+    let player_results = join_all(tasks).await;
+
+    for ((player_name, scores), (results, failures)) in players.into_iter().zip(player_results) {
+        // players whose scores couldnt be fetched only show up as a failure
+        if scores.is_ok() {
+            pp_results.results.insert(player_name, results);
+        }
+        pp_results.failures.extend(failures);
     }
 
     println!("Finished calculating PP for leaderboard.");
     Ok(pp_results)
-}
\ No newline at end of file
+}
+
+/// the events of `stream_pp_now`, dropping it stops the calculation and every fetch it started
+pub struct EventStream {
+    events: UnboundedReceiver<StreamEvent>,
+    _task: AbortOnDrop<()>,
+}
+
+impl Stream for EventStream {
+    type Item = StreamEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.events.poll_next_unpin(cx)
+    }
+}
+
+/// same as `calculate_pp_now`, but every player is sent down the stream as soon
+/// as their scores are done instead of waiting for the whole leaderboard
+pub fn stream_pp_now(
+    ctx: CalcContext,
+    mode: Mode,
+    calc_type: PPCalculationType,
+    options: LeaderboardOptions,
+) -> EventStream {
+    let (tx, rx) = mpsc::unbounded();
+
+    let task = tokio::spawn(async move {
+        let CalcContext { http, cache, beatmap_cache } = ctx;
+        println!("Streaming PP for leaderboard in mode {}", mode);
+
+        let leaderboard = match fetch_leaderboard(&http, mode, options, &cache).await {
+            Ok(leaderboard) => leaderboard,
+            Err(e) => {
+                let _ = tx.unbounded_send(StreamEvent::Error {
+                    player_name: None,
+                    error: e.info(),
+                });
+                return;
+            }
+        };
+
+        let _ = tx.unbounded_send(StreamEvent::Started { players: leaderboard.len() });
+
+        let beatmap_cache = &beatmap_cache;
+        let mut tasks: FuturesUnordered<_> = spawn_player_fetches(&http, leaderboard, mode, options, &cache)
+            .into_iter()
+            .map(|(player_name, fetch)| async move {
+                match join_player_fetch(fetch).await {
+                    Ok(scores) => {
+                        let (results, failures) = calculate_player_scores(beatmap_cache, &player_name, &scores, calc_type).await;
+                        StreamEvent::Player(PlayerResults { player_name, results, failures })
+                    },
+                    Err(e) => StreamEvent::Error {
+                        player_name: Some(player_name),
+                        error: e.info(),
+                    },
+                }
+            })
+            .collect();
+
+        // players are calculated concurrently and sent in whatever order they finish
+        while let Some(event) = tasks.next().await {
+            // the client went away, no point in calculating the rest
+            if tx.unbounded_send(event).is_err() {
+                println!("Stream closed by client, stopping.");
+                return;
+            }
+        }
+
+        let _ = tx.unbounded_send(StreamEvent::Done);
+        println!("Finished streaming PP for leaderboard.");
+    });
+
+    EventStream {
+        events: rx,
+        _task: AbortOnDrop(task),
+    }
+}
+
+/// runs the same leaderboard through every branch in one pass
+/// the leaderboard, scores and beatmaps are only fetched once
+pub async fn compare_pp_now(
+    ctx: &CalcContext,
+    mode: Mode,
+    version: Version,
+    rx: bool,
+    options: LeaderboardOptions,
+) -> Result<ComparisonResults, CalcError> {
+    println!("Comparing branches for leaderboard in mode {}", mode);
+
+    // outside of std the cheat values dont exist, so branches on the same revision
+    // calculate the exact same thing, only the first of them is run
+    let mut calc_types: Vec<(&str, PPCalculationType)> = Vec::new();
+    let mut comparisons = ComparisonResults::default();
+
+    for engine in registry().all() {
+        let same = calc_types.iter()
+            .find(|(_, calc_type)| mode.game_mode() != GameMode::Osu && calc_type.engine_revision() == engine.revision());
+
+        match same {
+            Some((name, _)) => {
+                comparisons.same_as.insert(engine.name().to_string(), name.to_string());
+            },
+            None => calc_types.push((engine.name(), PPCalculationType::new(engine, mode.game_mode(), version, rx))),
+        }
+    }
+
+    let (players, _) = fetch_leaderboard_scores(&ctx.http, mode, options, &ctx.cache).await?;
+    let beatmap_cache = &ctx.beatmap_cache;
+
+    let calc_types = &calc_types;
+    let tasks = players.iter().map(|(player_name, scores)| async move {
+        let scores = match scores {
+            Ok(scores) => scores,
+            Err(e) => return (Vec::new(), vec![player_failure(player_name, e)]),
+        };
+
+        let score_tasks = scores.iter().map(|score| async move {
+            let branch_tasks = calc_types.iter()
+                .map(|(_, calc_type)| calculate_score(beatmap_cache, score, player_name, *calc_type));
+            let branch_results = join_all(branch_tasks).await;
+
+            let mut pp = BTreeMap::new();
+            let mut stars = BTreeMap::new();
+            let mut mods = score.mods;
+
+            for ((branch_name, _), result) in calc_types.iter().zip(branch_results) {
+                let result = result.map_err(|e| score_failure(player_name, score, &e))?;
+                mods = result.mods;
+                pp.insert(branch_name.to_string(), result.recalculated_pp);
+                stars.insert(branch_name.to_string(), result.stars);
+            }
+
+            let mut differences = BTreeMap::new();
+            for (i, (a, _)) in calc_types.iter().enumerate() {
+                for (b, _) in calc_types.iter().skip(i + 1) {
+                    differences.insert(format!("{}-{}", a, b), round(pp[*a] - pp[*b], 2));
+                }
+            }
+
+            Ok::<_, Failure>(ScoreComparison {
+                beatmap_id: score.beatmap.id,
+                mods,
+                version: version.number(),
+                original_pp: round(score.pp, 2),
+                pp,
+                stars,
+                differences,
+            })
+        });
+
+        let mut player_comparisons = Vec::with_capacity(scores.len());
+        let mut failures = Vec::new();
+        for result in join_all(score_tasks).await {
+            match result {
+                Ok(comparison) => player_comparisons.push(comparison),
+                Err(failure) => failures.push(failure),
+            }
+        }
+
+        (player_comparisons, failures)
+    });
+    let player_comparisons = join_all(tasks).await;
+
+    for ((player_name, scores), (player_comparisons, failures)) in players.into_iter().zip(player_comparisons) {
+        if scores.is_ok() {
+            comparisons.results.insert(player_name, player_comparisons);
+        }
+        comparisons.failures.extend(failures);
+    }
+
+    println!("Finished comparing branches for leaderboard.");
+    Ok(comparisons)
+}
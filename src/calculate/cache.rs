@@ -1,30 +1,76 @@
 use std::collections::HashMap;
+use std::env;
 use std::sync::{Arc, RwLock};
 use std::time::{Duration, Instant};
 
+const DEFAULT_LEADERBOARD_TTL_SECS: u64 = 60;
+const DEFAULT_SCORES_TTL_SECS: u64 = 300;
+const DEFAULT_MAX_ENTRIES: usize = 10_000;
+
+/// what a cached response is, each kind goes stale at its own pace
+#[derive(Debug, Clone, Copy)]
+pub enum CacheKind {
+    Leaderboard,
+    PlayerScores,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct CacheConfig {
+    pub leaderboard_ttl: Duration,
+    pub scores_ttl: Duration,
+    pub max_entries: usize,
+}
+
+impl CacheConfig {
+    /// `RESPONSE_CACHE_LEADERBOARD_TTL_SECS`, `RESPONSE_CACHE_SCORES_TTL_SECS` and `RESPONSE_CACHE_MAX_ENTRIES`
+    pub fn from_env() -> Self {
+        fn var<T: std::str::FromStr>(name: &str, default: T) -> T {
+            env::var(name)
+                .ok()
+                .and_then(|v| v.parse::<T>().ok())
+                .unwrap_or(default)
+        }
+
+        Self {
+            leaderboard_ttl: Duration::from_secs(var("RESPONSE_CACHE_LEADERBOARD_TTL_SECS", DEFAULT_LEADERBOARD_TTL_SECS)),
+            scores_ttl: Duration::from_secs(var("RESPONSE_CACHE_SCORES_TTL_SECS", DEFAULT_SCORES_TTL_SECS)),
+            max_entries: var("RESPONSE_CACHE_MAX_ENTRIES", DEFAULT_MAX_ENTRIES),
+        }
+    }
+
+    fn ttl(&self, kind: CacheKind) -> Duration {
+        match kind {
+            CacheKind::Leaderboard => self.leaderboard_ttl,
+            CacheKind::PlayerScores => self.scores_ttl,
+        }
+    }
+}
+
+/// upstream api responses keyed by url, shared by every request
 #[derive(Clone)]
 pub struct Cache {
     data: Arc<RwLock<HashMap<String, CacheEntry>>>,
-    cache_duration: Duration,
+    config: CacheConfig,
 }
 
 struct CacheEntry {
     value: String,
     timestamp: Instant,
+    expires_at: Instant,
 }
 
 impl Cache {
-    pub fn new(duration_secs: u64) -> Self {
+    pub fn new(config: CacheConfig) -> Self {
         Cache {
             data: Arc::new(RwLock::new(HashMap::new())),
-            cache_duration: Duration::from_secs(duration_secs),
+            config,
         }
     }
 
     pub fn get(&self, key: &str) -> Option<String> {
         let cache = self.data.read().unwrap();
         if let Some(entry) = cache.get(key) {
-            if entry.timestamp.elapsed() < self.cache_duration {
+            if Instant::now() < entry.expires_at {
                 println!("Cache hit for key: {}", key);
                 return Some(entry.value.clone());
             } else {
@@ -34,15 +80,84 @@ impl Cache {
         None
     }
 
-    pub fn set(&self, key: &str, value: String) {
+    pub fn set(&self, kind: CacheKind, key: &str, value: String) {
+        let ttl = self.config.ttl(kind);
+        if ttl.is_zero() || self.config.max_entries == 0 {
+            return;
+        }
+
+        let now = Instant::now();
         let mut cache = self.data.write().unwrap();
         cache.insert(key.to_string(), CacheEntry {
             value,
-            timestamp: Instant::now(),
+            timestamp: now,
+            expires_at: now + ttl,
         });
 
-        cache.retain(|_, entry| entry.timestamp.elapsed() < self.cache_duration);
-        
+        cache.retain(|_, entry| now < entry.expires_at);
+
+        // still full of fresh entries, the oldest ones go first
+        if cache.len() > self.config.max_entries {
+            let mut by_age: Vec<(Instant, String)> = cache.iter()
+                .map(|(key, entry)| (entry.timestamp, key.clone()))
+                .collect();
+            by_age.sort();
+
+            let excess = cache.len() - self.config.max_entries;
+            for (_, key) in by_age.into_iter().take(excess) {
+                cache.remove(&key);
+            }
+        }
+
         println!("Cache updated for key: {}", key);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn cache(leaderboard_ttl: Duration, scores_ttl: Duration, max_entries: usize) -> Cache {
+        Cache::new(CacheConfig { leaderboard_ttl, scores_ttl, max_entries })
+    }
+
+    #[test]
+    fn each_kind_has_its_own_ttl() {
+        let cache = cache(Duration::from_millis(50), Duration::from_secs(60), 10);
+        cache.set(CacheKind::Leaderboard, "leaderboard", "a".to_string());
+        cache.set(CacheKind::PlayerScores, "scores", "b".to_string());
+
+        assert_eq!(cache.get("leaderboard").as_deref(), Some("a"));
+        thread::sleep(Duration::from_millis(100));
+
+        assert_eq!(cache.get("leaderboard"), None);
+        assert_eq!(cache.get("scores").as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn oldest_entries_go_first_when_full() {
+        let cache = cache(Duration::from_secs(60), Duration::from_secs(60), 2);
+        cache.set(CacheKind::PlayerScores, "a", "1".to_string());
+        cache.set(CacheKind::PlayerScores, "b", "2".to_string());
+        cache.set(CacheKind::PlayerScores, "c", "3".to_string());
+
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(cache.get("b").as_deref(), Some("2"));
+        assert_eq!(cache.get("c").as_deref(), Some("3"));
+    }
+
+    #[test]
+    fn zero_ttl_or_size_turns_caching_off() {
+        let cache = cache(Duration::ZERO, Duration::from_secs(60), 10);
+        cache.set(CacheKind::Leaderboard, "leaderboard", "a".to_string());
+        cache.set(CacheKind::PlayerScores, "scores", "b".to_string());
+        assert_eq!(cache.get("leaderboard"), None);
+        assert_eq!(cache.get("scores").as_deref(), Some("b"));
+
+        let cache = self::cache(Duration::from_secs(60), Duration::from_secs(60), 0);
+        cache.set(CacheKind::PlayerScores, "scores", "b".to_string());
+        assert_eq!(cache.get("scores"), None);
+    }
+}
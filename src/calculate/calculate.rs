@@ -1,244 +1,52 @@
-
-/// this only calculates standard
 /// used to calculate reworks and future updates on the
-/// std pp system
+/// std pp system, taiko, catch and mania go to their own calculators
 
-use std::error::Error;
+use crate::error::CalcError;
 use crate::models::{PlayerScore, PPCalculationResult};
+use crate::calculate::engine::{PpEngine, PpOutput};
+use crate::calculate::parsed::MapSource;
+use crate::calculate::params::{GameMode, NamedParam, Version};
 use crate::calculate::utils::round;
 
-use refx_pp_rs::{Beatmap, BeatmapExt};
-use if_servers_legit::{Beatmap as ifLegitBeatmap, BeatmapExt as ifLegitExt};
-use live_pp::{Beatmap as livePPBeatmap, BeatmapExt as livePPExt};
-
+/// which engine, mode and version a score is recalculated with
 #[derive(Clone, Copy)]
-pub enum PPCalculationType {
-
-    /// main without cv (cheat value)
-    VanillaNoCV,
-    RelaxNoCV,
-    ScoreV2NoCV { relax: bool },
-
-    /// main with cv (cheat value)
-    VanillaCheats,
-    RelaxCheats,
-    ScoreV2Cheats { relax: bool },
-
-    /// if-servers-legit
-    VanillaLegit,
-    RelaxLegit,
-    ScoreV2Legit { relax: bool },
+pub struct PPCalculationType {
+    pub engine: &'static dyn PpEngine,
+    pub mode: GameMode,
+    pub version: Version,
+    /// only used by scorev2, vanilla and relax are their own versions
+    pub rx: bool,
+}
 
-    /// live pp
-    VanillaCheatsLive,
-    RelaxCheatsLive,
-    ScoreV2CheatsLive { relax: bool },
+impl PPCalculationType {
+    pub fn new(engine: &'static dyn PpEngine, mode: GameMode, version: Version, rx: bool) -> Self {
+        Self { engine, mode, version, rx }
+    }
 
+    /// git revision of the refx-pp crate this calculation runs on
+    pub fn engine_revision(&self) -> &'static str {
+        self.engine.revision()
+    }
 }
 
-pub async fn calculate_pp(
-    beatmap_path: &str,
+/// cpu heavy and blocking, runs on the calculation pool (see `pool.rs`)
+/// `beatmap` has to be prepared for `calc_type.engine.map_kind()` beforehand
+pub fn calculate_pp(
+    beatmap: &MapSource,
     score: &PlayerScore,
     player_name: &str,
     calc_type: PPCalculationType,
-) -> Result<PPCalculationResult, Box<dyn Error>> {
+) -> Result<PPCalculationResult, CalcError> {
     println!(
-        "Calculating PP for player '{}' using beatmap path '{}'",
-        player_name, beatmap_path
+        "Calculating PP for player '{}' on beatmap {} with '{}'",
+        player_name, beatmap.beatmap_id(), calc_type.engine.name()
     );
 
-    let original_pp = round(score.pp, 2);
-    let (recalculated_pp, stars, mods) = match calc_type {
-
-        PPCalculationType::VanillaNoCV => {
-            let map = Beatmap::from_path(beatmap_path)?;
-            let result = map.pp()
-                .mods(score.mods)
-                .combo(score.max_combo)
-                .accuracy(score.acc)
-                .n300(score.n300)
-                .n100(score.n100)
-                .n50(score.n50)
-                .n_misses(score.nmiss)
-                .calculate();
-            (result.pp(), result.stars(), score.mods)
-        },
-
-        PPCalculationType::RelaxNoCV => {
-            let map = Beatmap::from_path(beatmap_path)?;
-            let result = refx_pp_rs::osu_2019::OsuPP::new(&map)
-                .mods(score.mods)
-                .combo(score.max_combo)
-                .accuracy(score.acc as f32)
-                .n300(score.n300)
-                .n100(score.n100)
-                .n50(score.n50)
-                .misses(score.nmiss)
-                .calculate();
-            (result.pp, result.difficulty.stars, score.mods)
-        },
-
-        // shouldnt needed?
-        // theres no change here
-        // wait nvm there is
-        PPCalculationType::ScoreV2NoCV { relax } => {
-            let map = Beatmap::from_path(beatmap_path)?;
-            let mods = score.mods | if relax { 1 << 7 } else { 0 };
-            let result = refx_pp_rs::osu_2019_2::FxPP::new_from_map(&map)
-                .mods(mods)
-                .combo(score.max_combo)
-                .accuracy(score.acc as f32)
-                .n300(score.n300)
-                .n100(score.n100)
-                .n50(score.n50)
-                .misses(score.nmiss)
-                .calculate();
-            (result.pp, result.difficulty.stars, mods)
-        },
-
-        PPCalculationType::VanillaCheats => {
-            let map = Beatmap::from_path(beatmap_path)?;
-            let result = map.pp()
-                .mods(score.mods)
-                .combo(score.max_combo)
-                .accuracy(score.acc)
-                .n300(score.n300)
-                .n100(score.n100)
-                .n50(score.n50)
-                .n_misses(score.nmiss)
-                .ac(score.aim_value)
-                .arc(score.ar_value)
-                .hdr(score.hdr != 0)
-                .calculate();
-            (result.pp(), result.stars(), score.mods)
-        },
-
-        PPCalculationType::RelaxCheats => {
-            let map = Beatmap::from_path(beatmap_path)?;
-            let result = refx_pp_rs::osu_2019::OsuPP::new(&map)
-                .mods(score.mods)
-                .combo(score.max_combo)
-                .accuracy(score.acc as f32)
-                .n300(score.n300)
-                .n100(score.n100)
-                .n50(score.n50)
-                .misses(score.nmiss)
-                .ac(score.aim_value)
-                .arc(score.ar_value)
-                .tw(score.twval as usize)
-                .cs(score.cs != 0)
-                .calculate();
-            (result.pp, result.difficulty.stars, score.mods)
-        },
-
-        PPCalculationType::ScoreV2Cheats { relax } => {
-            let map = Beatmap::from_path(beatmap_path)?;
-            let mods = score.mods | if relax { 1 << 7 } else { 0 };
-            let result = refx_pp_rs::osu_2019_2::FxPP::new_from_map(&map)
-                .mods(mods)
-                .combo(score.max_combo)
-                .accuracy(score.acc as f32)
-                .n300(score.n300)
-                .n100(score.n100)
-                .n50(score.n50)
-                .misses(score.nmiss)
-                .calculate();
-            (result.pp, result.difficulty.stars, mods)
-        },
+    calc_type.mode.check_version(calc_type.version).map_err(CalcError::InvalidInput)?;
 
-        PPCalculationType::VanillaLegit => {
-            let map = ifLegitBeatmap::from_path(beatmap_path).await?;
-            let result = map.pp()
-                .mods(score.mods)
-                .combo(score.max_combo)
-                .accuracy(score.acc)
-                .n300(score.n300)
-                .n100(score.n100)
-                .n50(score.n50)
-                .n_misses(score.nmiss)
-                .calculate();
-            (result.pp(), result.stars(), score.mods)
-        },
-
-        PPCalculationType::RelaxLegit => {
-            let map = ifLegitBeatmap::from_path(beatmap_path).await?;
-            let result = if_servers_legit::osu_2019::OsuPP::new(&map)
-                .mods(score.mods)
-                .combo(score.max_combo)
-                .accuracy(score.acc as f32)
-                .n300(score.n300)
-                .n100(score.n100)
-                .n50(score.n50)
-                .misses(score.nmiss)
-                .calculate();
-            (result.pp, result.difficulty.stars, score.mods)
-        },
-
-        PPCalculationType::ScoreV2Legit { relax } => {
-            let map = ifLegitBeatmap::from_path(beatmap_path).await?;
-            let mods = score.mods | if relax { 1 << 7 } else { 0 };
-            let result = if_servers_legit::osu_2019_scorev2::FxPP::new_from_map(&map)
-                .mods(mods)
-                .combo(score.max_combo)
-                .accuracy(score.acc as f32)
-                .n300(score.n300)
-                .n100(score.n100)
-                .n50(score.n50)
-                .misses(score.nmiss)
-                .calculate();
-            (result.pp, result.difficulty.stars, mods)
-        },
-        
-        PPCalculationType::VanillaCheatsLive => {
-            let map = livePPBeatmap::from_path(beatmap_path).await?;
-            let result = map.pp()
-                .mods(score.mods)
-                .combo(score.max_combo)
-                .accuracy(score.acc)
-                .n300(score.n300)
-                .n100(score.n100)
-                .n50(score.n50)
-                .n_misses(score.nmiss)
-                .ac(score.aim_value)
-                .arc(score.ar_value)
-                .hdr(score.hdr != 0)
-                .calculate();
-            (result.pp(), result.stars(), score.mods)
-        },
-
-        PPCalculationType::RelaxCheatsLive => {
-            let map = livePPBeatmap::from_path(beatmap_path).await?;
-            let result = live_pp::osu_2019::OsuPP::new(&map)
-                .mods(score.mods)
-                .combo(score.max_combo)
-                .accuracy(score.acc as f32)
-                .n300(score.n300)
-                .n100(score.n100)
-                .n50(score.n50)
-                .misses(score.nmiss)
-                .ac(score.aim_value)
-                .arc(score.ar_value)
-                .tw(score.twval as usize)
-                .cs(score.cs != 0)
-                .calculate();
-            (result.pp, result.difficulty.stars, score.mods)
-        },
-
-        PPCalculationType::ScoreV2CheatsLive { relax } => {
-            let map = livePPBeatmap::from_path(beatmap_path).await?;
-            let mods = score.mods | if relax { 1 << 7 } else { 0 };
-            let result = live_pp::osu_2019_2::FxPP::new_from_map(&map)
-                .mods(mods)
-                .combo(score.max_combo)
-                .accuracy(score.acc as f32)
-                .n300(score.n300)
-                .n100(score.n100)
-                .n50(score.n50)
-                .misses(score.nmiss)
-                .calculate();
-            (result.pp, result.difficulty.stars, mods)
-        },
-    };
+    let original_pp = round(score.pp, 2);
+    let PpOutput { pp: recalculated_pp, stars, mods } = calc_type.engine
+        .calculate(beatmap, score, calc_type.mode, calc_type.version, calc_type.rx)?;
 
     let mut final_pp = round(recalculated_pp, 2);
     let mut final_stars = round(stars, 2);
@@ -268,13 +76,26 @@ pub async fn calculate_pp(
         recalculated_pp: final_pp,
         difference,
         mods,
-        version: match calc_type {
-            PPCalculationType::VanillaNoCV | PPCalculationType::VanillaCheats | 
-            PPCalculationType::VanillaLegit | PPCalculationType::VanillaCheatsLive => 0,
-            PPCalculationType::RelaxNoCV | PPCalculationType::RelaxCheats | 
-            PPCalculationType::RelaxLegit | PPCalculationType::RelaxCheatsLive => 1,
-            PPCalculationType::ScoreV2NoCV { .. } | PPCalculationType::ScoreV2Cheats { .. } | 
-            PPCalculationType::ScoreV2Legit { .. } | PPCalculationType::ScoreV2CheatsLive { .. } => 2,
-        },
+        version: calc_type.version.number(),
+        engine_revision: calc_type.engine_revision().to_string(),
     })
-}
\ No newline at end of file
+}
+
+/// star rating only, for when there is no score to recalculate
+/// cpu heavy like `calculate_pp`, `beatmap` has to be prepared the same way
+pub fn calculate_difficulty(
+    beatmap: &MapSource,
+    mods: u32,
+    calc_type: PPCalculationType,
+) -> Result<f64, CalcError> {
+    calc_type.mode.check_version(calc_type.version).map_err(CalcError::InvalidInput)?;
+
+    let stars = round(calc_type.engine.difficulty(beatmap, calc_type.mode, calc_type.version, mods)?, 2);
+
+    if stars.is_infinite() || stars.is_nan() {
+        println!("Calculated stars is infinite or NaN");
+        return Ok(0.0);
+    }
+
+    Ok(stars)
+}
@@ -0,0 +1,308 @@
+/// compares two stored runs without recalculating either of them
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::models::{
+    BeatmapDelta,
+    Failure,
+    PlayerDelta,
+    PPCalculationResult,
+    RunDiff,
+    RunScore,
+    ScoreDelta,
+};
+use crate::calculate::totals::total_pp;
+use crate::calculate::utils::round;
+
+type Results = HashMap<String, Vec<PPCalculationResult>>;
+
+// a score is the same score in both runs if it is the same player, map and mods
+type ScoreKey = (String, u64, u32);
+
+fn index_scores(results: &Results) -> HashMap<ScoreKey, &PPCalculationResult> {
+    results.iter()
+        .flat_map(|(player_name, scores)| {
+            scores.iter().map(move |s| ((player_name.clone(), s.beatmap_id, s.mods), s))
+        })
+        .collect()
+}
+
+/// what couldnt be calculated in a run, a failed score only has its map to go by
+/// since its mods can differ from the result's (scorev2 adds relax)
+#[derive(Default)]
+struct Failed {
+    scores: HashSet<(String, u64)>,
+    /// players whose scores couldnt be fetched at all
+    players: HashSet<String>,
+}
+
+impl Failed {
+    fn new(failures: &[Failure]) -> Self {
+        let mut failed = Self::default();
+        for failure in failures {
+            let Some(player_name) = &failure.player_name else {
+                continue;
+            };
+            match failure.beatmap_id {
+                Some(beatmap_id) => failed.scores.insert((player_name.clone(), beatmap_id)),
+                None => failed.players.insert(player_name.clone()),
+            };
+        }
+        failed
+    }
+
+    fn contains(&self, player_name: &str, beatmap_id: u64) -> bool {
+        self.players.contains(player_name) || self.scores.contains(&(player_name.to_string(), beatmap_id))
+    }
+}
+
+fn by_delta(a: f64, b: f64) -> Ordering {
+    b.abs().partial_cmp(&a.abs()).unwrap_or(Ordering::Equal)
+}
+
+/// per score, per player and per beatmap deltas going from run a to run b
+/// a score missing from one run because it failed there isnt a change, it goes to `failed`
+/// and is left out of the player totals on both sides
+pub fn diff_runs(
+    run_a: i64,
+    a: &Results,
+    failures_a: &[Failure],
+    run_b: i64,
+    b: &Results,
+    failures_b: &[Failure],
+) -> RunDiff {
+    let scores_a = index_scores(a);
+    let scores_b = index_scores(b);
+    let failed_a = Failed::new(failures_a);
+    let failed_b = Failed::new(failures_b);
+    let failed_in_either = |player_name: &str, beatmap_id: u64| {
+        failed_a.contains(player_name, beatmap_id) || failed_b.contains(player_name, beatmap_id)
+    };
+
+    let mut scores: Vec<ScoreDelta> = Vec::new();
+    let mut appeared: Vec<RunScore> = Vec::new();
+    let mut disappeared: Vec<RunScore> = Vec::new();
+    let mut failed: Vec<RunScore> = Vec::new();
+    let mut beatmaps: BTreeMap<u64, (usize, f64)> = BTreeMap::new();
+
+    for (key, result_b) in &scores_b {
+        match scores_a.get(key) {
+            Some(result_a) => {
+                let delta = round(result_b.recalculated_pp - result_a.recalculated_pp, 2);
+
+                let beatmap = beatmaps.entry(result_b.beatmap_id).or_insert((0, 0.0));
+                beatmap.0 += 1;
+                beatmap.1 += delta;
+
+                scores.push(ScoreDelta {
+                    player_name: key.0.clone(),
+                    result: (*result_b).clone(),
+                    delta,
+                });
+            },
+            None => {
+                let score = RunScore {
+                    player_name: key.0.clone(),
+                    result: (*result_b).clone(),
+                };
+                if failed_a.contains(&key.0, key.1) {
+                    failed.push(score);
+                } else {
+                    appeared.push(score);
+                }
+            },
+        }
+    }
+
+    for (key, result_a) in &scores_a {
+        if !scores_b.contains_key(key) {
+            let score = RunScore {
+                player_name: key.0.clone(),
+                result: (*result_a).clone(),
+            };
+            if failed_b.contains(&key.0, key.1) {
+                failed.push(score);
+            } else {
+                disappeared.push(score);
+            }
+        }
+    }
+
+    // a player that couldnt be fetched in one run has no total to compare there
+    let player_names: HashSet<&String> = a.keys().chain(b.keys())
+        .filter(|player_name| !failed_a.players.contains(*player_name) && !failed_b.players.contains(*player_name))
+        .collect();
+    let mut players: Vec<PlayerDelta> = player_names.into_iter()
+        .map(|player_name| {
+            let total = |results: &Results| {
+                let pps = results.get(player_name)
+                    .map(|scores| scores.iter()
+                        .filter(|s| !failed_in_either(player_name, s.beatmap_id))
+                        .map(|s| s.recalculated_pp)
+                        .collect())
+                    .unwrap_or_default();
+                round(total_pp(pps), 2)
+            };
+            let total_a = total(a);
+            let total_b = total(b);
+
+            PlayerDelta {
+                player_name: player_name.clone(),
+                total_a,
+                total_b,
+                delta: round(total_b - total_a, 2),
+            }
+        })
+        .collect();
+
+    let mut beatmaps: Vec<BeatmapDelta> = beatmaps.into_iter()
+        .map(|(beatmap_id, (count, total_delta))| BeatmapDelta {
+            beatmap_id,
+            scores: count,
+            total_delta: round(total_delta, 2),
+            average_delta: round(total_delta / count as f64, 2),
+        })
+        .collect();
+
+    // biggest movers first
+    scores.sort_by(|x, y| by_delta(x.delta, y.delta));
+    players.sort_by(|x, y| by_delta(x.delta, y.delta));
+    beatmaps.sort_by(|x, y| by_delta(x.average_delta, y.average_delta));
+    appeared.sort_by(|x, y| (&x.player_name, x.result.beatmap_id).cmp(&(&y.player_name, y.result.beatmap_id)));
+    disappeared.sort_by(|x, y| (&x.player_name, x.result.beatmap_id).cmp(&(&y.player_name, y.result.beatmap_id)));
+    failed.sort_by(|x, y| (&x.player_name, x.result.beatmap_id).cmp(&(&y.player_name, y.result.beatmap_id)));
+
+    RunDiff {
+        run_a,
+        run_b,
+        scores,
+        players,
+        beatmaps,
+        appeared,
+        disappeared,
+        failed,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::models::ErrorInfo;
+
+    fn failure(player_name: &str, beatmap_id: Option<u64>) -> Failure {
+        Failure {
+            player_name: Some(player_name.to_string()),
+            beatmap_id,
+            mods: beatmap_id.map(|_| 0),
+            error: ErrorInfo {
+                code: "beatmap_timeout".to_string(),
+                message: "timed out".to_string(),
+            },
+        }
+    }
+
+    fn result(beatmap_id: u64, mods: u32, recalculated_pp: f64) -> PPCalculationResult {
+        PPCalculationResult {
+            stars: 5.0,
+            beatmap_id,
+            original_pp: 100.0,
+            recalculated_pp,
+            difference: recalculated_pp - 100.0,
+            mods,
+            version: 0,
+            engine_revision: String::new(),
+        }
+    }
+
+    #[test]
+    fn matches_scores_by_player_map_and_mods() {
+        let a = HashMap::from([
+            ("cookiezi".to_string(), vec![result(1, 0, 100.0), result(2, 0, 200.0), result(3, 0, 50.0)]),
+            ("rafis".to_string(), vec![result(1, 0, 90.0)]),
+        ]);
+        let b = HashMap::from([
+            // same map with different mods is another score
+            ("cookiezi".to_string(), vec![result(1, 0, 110.0), result(2, 0, 150.0), result(3, 64, 60.0)]),
+            ("rafis".to_string(), vec![result(1, 0, 90.0)]),
+        ]);
+
+        let diff = diff_runs(1, &a, &[], 2, &b, &[]);
+
+        assert_eq!((diff.run_a, diff.run_b), (1, 2));
+        assert_eq!(diff.scores.len(), 3);
+        // biggest movers first
+        assert_eq!((diff.scores[0].result.beatmap_id, diff.scores[0].delta), (2, -50.0));
+        assert_eq!((diff.scores[1].result.beatmap_id, diff.scores[1].delta), (1, 10.0));
+        assert_eq!(diff.scores[2].delta, 0.0);
+
+        assert_eq!(diff.appeared.len(), 1);
+        assert_eq!((diff.appeared[0].result.beatmap_id, diff.appeared[0].result.mods), (3, 64));
+        assert_eq!(diff.disappeared.len(), 1);
+        assert_eq!((diff.disappeared[0].result.beatmap_id, diff.disappeared[0].result.mods), (3, 0));
+    }
+
+    #[test]
+    fn sums_deltas_per_beatmap_and_player() {
+        let a = HashMap::from([
+            ("a".to_string(), vec![result(1, 0, 100.0)]),
+            ("b".to_string(), vec![result(1, 0, 100.0), result(2, 0, 100.0)]),
+        ]);
+        let b = HashMap::from([
+            ("a".to_string(), vec![result(1, 0, 120.0)]),
+            ("b".to_string(), vec![result(1, 0, 110.0), result(2, 0, 101.0)]),
+        ]);
+
+        let diff = diff_runs(1, &a, &[], 2, &b, &[]);
+
+        assert_eq!(diff.beatmaps.len(), 2);
+        assert_eq!(diff.beatmaps[0].beatmap_id, 1);
+        assert_eq!(diff.beatmaps[0].scores, 2);
+        assert_eq!(diff.beatmaps[0].total_delta, 30.0);
+        assert_eq!(diff.beatmaps[0].average_delta, 15.0);
+        assert_eq!(diff.beatmaps[1].average_delta, 1.0);
+
+        assert_eq!(diff.players[0].player_name, "a");
+        assert_eq!(diff.players[0].delta, 20.0);
+        assert_eq!(diff.players[0].total_a, round(total_pp(vec![100.0]), 2));
+    }
+
+    #[test]
+    fn a_player_missing_from_one_run_has_a_zero_total_there() {
+        let a = HashMap::new();
+        let b = HashMap::from([("new".to_string(), vec![result(1, 0, 100.0)])]);
+
+        let diff = diff_runs(1, &a, &[], 2, &b, &[]);
+
+        assert!(diff.scores.is_empty());
+        assert_eq!(diff.appeared.len(), 1);
+        assert_eq!(diff.players[0].total_a, 0.0);
+        assert_eq!(diff.players[0].total_b, round(total_pp(vec![100.0]), 2));
+    }
+
+    #[test]
+    fn failed_scores_are_not_changes() {
+        let a = HashMap::from([
+            ("a".to_string(), vec![result(1, 0, 100.0), result(2, 0, 200.0)]),
+            ("b".to_string(), vec![result(1, 0, 100.0)]),
+        ]);
+        let b = HashMap::from([
+            ("a".to_string(), vec![result(1, 0, 110.0), result(3, 0, 50.0)]),
+        ]);
+        let failures_a = [failure("a", Some(3))];
+        let failures_b = [failure("a", Some(2)), failure("b", None)];
+
+        let diff = diff_runs(1, &a, &failures_a, 2, &b, &failures_b);
+
+        assert!(diff.appeared.is_empty());
+        assert!(diff.disappeared.is_empty());
+        assert_eq!(diff.failed.len(), 3);
+
+        // b couldnt be fetched in run b, a's totals only count the map both runs have
+        assert_eq!(diff.players.len(), 1);
+        assert_eq!(diff.players[0].player_name, "a");
+        assert_eq!(diff.players[0].delta, 10.0);
+    }
+}
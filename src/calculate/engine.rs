@@ -0,0 +1,139 @@
+/// a pp engine is one refx-pp revision (or variant of one), registered under its branch name
+/// adding a revision means adding the crate to Cargo.toml, implementing `PpEngine`
+/// for it in `engines.rs` and registering it below
+
+use std::sync::OnceLock;
+
+use crate::error::CalcError;
+use crate::models::{BranchInfo, PlayerScore};
+use crate::calculate::params::{GameMode, NamedParam, Version};
+use crate::calculate::parsed::{MapKind, MapSource};
+use crate::calculate::engines::{LegitEngine, LiveEngine, RefxEngine};
+
+/// what an engine gives back for one score, before rounding
+pub struct PpOutput {
+    pub pp: f64,
+    pub stars: f64,
+    /// the mods actually used, scorev2 adds relax to them
+    pub mods: u32,
+}
+
+/// what `PpEngine::parse` found out about a map
+pub struct MapInfo {
+    pub hit_objects: usize,
+    /// the mode the map was made for, std maps are only converted once a score asks for it
+    pub mode: GameMode,
+}
+
+/// engines are stateless, the parsed maps live on the `MapSource`
+/// everything here is cpu bound and must run on the calculation pool
+pub trait PpEngine: Send + Sync {
+    /// the branch name it is registered under, e.g. "live"
+    fn name(&self) -> &'static str;
+    /// git revision of the crate, see Cargo.toml
+    fn revision(&self) -> &'static str;
+    /// one line for /branches
+    fn description(&self) -> &'static str;
+    /// whether the cheat values of a score (aim_value, ar_value, ...) are used
+    fn cheats(&self) -> bool;
+    /// versions `calculate` accepts, all of them unless overridden
+    fn versions(&self) -> &'static [Version] {
+        Version::ALL
+    }
+    /// which Beatmap type it reads, so `MapSource::prepare` knows what to load
+    fn map_kind(&self) -> MapKind;
+
+    /// parses the map (or takes it from the parsed cache)
+    fn parse(&self, beatmap: &MapSource) -> Result<MapInfo, CalcError>;
+    /// star rating with `mods` under `version`, no score needed
+    fn difficulty(&self, beatmap: &MapSource, mode: GameMode, version: Version, mods: u32) -> Result<f64, CalcError>;
+    /// `rx` only matters for scorev2, vanilla and relax are their own versions
+    /// `version` only picks the std formula, other modes always use their own calculator
+    /// (converting std maps if needed)
+    fn calculate(
+        &self,
+        beatmap: &MapSource,
+        score: &PlayerScore,
+        mode: GameMode,
+        version: Version,
+        rx: bool,
+    ) -> Result<PpOutput, CalcError>;
+}
+
+/// an engine picked by `branch=`, `number` is its position in the registry
+#[derive(Clone, Copy)]
+pub struct Branch {
+    pub number: u8,
+    pub engine: &'static dyn PpEngine,
+}
+
+pub struct EngineRegistry {
+    engines: Vec<&'static dyn PpEngine>,
+}
+
+impl EngineRegistry {
+    fn new() -> Self {
+        // the position is the branch number old clients send, only ever append
+        // the first one is the default branch
+        Self {
+            engines: vec![
+                &LiveEngine,
+                &RefxEngine { cheats: true },
+                &RefxEngine { cheats: false },
+                &LegitEngine,
+            ],
+        }
+    }
+
+    fn branches(&self) -> impl Iterator<Item = Branch> + '_ {
+        self.engines.iter()
+            .enumerate()
+            .map(|(number, engine)| Branch { number: number as u8, engine: *engine })
+    }
+
+    pub fn default_branch(&self) -> Branch {
+        Branch { number: 0, engine: self.engines[0] }
+    }
+
+    /// a branch name (case insensitive) or its number, anything else lists what is allowed
+    pub fn resolve(&self, value: &str) -> Result<Branch, String> {
+        let value = value.trim();
+        let found = match value.parse::<u8>() {
+            Ok(number) => self.branches().find(|branch| branch.number == number),
+            Err(_) => self.branches().find(|branch| branch.engine.name().eq_ignore_ascii_case(value)),
+        };
+
+        found.ok_or_else(|| {
+            let allowed = self.branches()
+                .map(|branch| format!("{} ({})", branch.engine.name(), branch.number))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!("Invalid branch '{}'. Allowed values: {}.", value, allowed)
+        })
+    }
+
+    pub fn all(&self) -> impl Iterator<Item = &'static dyn PpEngine> + '_ {
+        self.engines.iter().copied()
+    }
+
+    pub fn info(&self) -> Vec<BranchInfo> {
+        self.branches()
+            .map(|Branch { number, engine }| BranchInfo {
+                name: engine.name().to_string(),
+                number,
+                description: engine.description().to_string(),
+                versions: engine.versions().iter()
+                    .map(|version| version.name().to_string())
+                    .collect(),
+                cheats: engine.cheats(),
+                revision: engine.revision().to_string(),
+            })
+            .collect()
+    }
+}
+
+static REGISTRY: OnceLock<EngineRegistry> = OnceLock::new();
+
+pub fn registry() -> &'static EngineRegistry {
+    REGISTRY.get_or_init(EngineRegistry::new)
+}
@@ -0,0 +1,511 @@
+/// the engine crates from Cargo.toml, see `engine.rs` for how they are used
+
+use crate::error::CalcError;
+use crate::models::PlayerScore;
+use crate::calculate::engine::{MapInfo, PpEngine, PpOutput};
+use crate::calculate::params::{GameMode, Version};
+use crate::calculate::parsed::{MapKind, MapSource};
+
+use refx_pp_rs::BeatmapExt;
+use if_servers_legit::BeatmapExt as ifLegitExt;
+use live_pp::BeatmapExt as livePPExt;
+
+// set by build.rs from the revs in Cargo.toml
+pub const REFX_PP_REVISION: &str = env!("REFX_PP_REVISION");
+pub const IF_SERVERS_LEGIT_REVISION: &str = env!("IF_SERVERS_LEGIT_REVISION");
+pub const LIVE_PP_REVISION: &str = env!("LIVE_PP_REVISION");
+
+fn scorev2_mods(mods: u32, relax: bool) -> u32 {
+    mods | if relax { 1 << 7 } else { 0 }
+}
+
+macro_rules! impl_game_mode {
+    ($($krate:ident),*) => {$(
+        impl From<GameMode> for $krate::GameMode {
+            fn from(mode: GameMode) -> Self {
+                match mode {
+                    GameMode::Osu => $krate::GameMode::Osu,
+                    GameMode::Taiko => $krate::GameMode::Taiko,
+                    GameMode::Catch => $krate::GameMode::Catch,
+                    GameMode::Mania => $krate::GameMode::Mania,
+                }
+            }
+        }
+
+        impl From<$krate::GameMode> for GameMode {
+            fn from(mode: $krate::GameMode) -> Self {
+                match mode {
+                    $krate::GameMode::Osu => GameMode::Osu,
+                    $krate::GameMode::Taiko => GameMode::Taiko,
+                    $krate::GameMode::Catch => GameMode::Catch,
+                    $krate::GameMode::Mania => GameMode::Mania,
+                }
+            }
+        }
+    )*};
+}
+
+impl_game_mode!(refx_pp_rs, if_servers_legit, live_pp);
+
+/// taiko, catch and mania, the same in every engine since only std was reworked
+/// `mode` converts std maps, maps made for another mode keep their own
+/// cheat values only exist for std and are ignored
+macro_rules! calculate_other_mode {
+    ($map:expr, $score:expr, $mode:expr) => {{
+        let result = $map.pp()
+            .mode($mode.into())
+            .mods($score.mods)
+            .combo($score.max_combo)
+            .accuracy($score.acc)
+            .n300($score.n300)
+            .n100($score.n100)
+            .n50($score.n50)
+            .n_geki($score.ngeki)
+            .n_katu($score.nkatu)
+            .n_misses($score.nmiss)
+            .calculate();
+        PpOutput { pp: result.pp(), stars: result.stars(), mods: $score.mods }
+    }};
+}
+
+/// refx-pp-rs main, with or without cv (cheat value)
+pub struct RefxEngine {
+    pub cheats: bool,
+}
+
+impl PpEngine for RefxEngine {
+    fn name(&self) -> &'static str {
+        if self.cheats { "cv" } else { "nocv" }
+    }
+
+    fn description(&self) -> &'static str {
+        if self.cheats { "refx-pp-rs main with cv (cheat value)" } else { "refx-pp-rs main without cv" }
+    }
+
+    fn cheats(&self) -> bool {
+        self.cheats
+    }
+
+    fn revision(&self) -> &'static str {
+        REFX_PP_REVISION
+    }
+
+    fn map_kind(&self) -> MapKind {
+        MapKind::Refx
+    }
+
+    fn parse(&self, beatmap: &MapSource) -> Result<MapInfo, CalcError> {
+        let map = beatmap.refx()?;
+        Ok(MapInfo { hit_objects: map.hit_objects.len(), mode: map.mode.into() })
+    }
+
+    fn difficulty(&self, beatmap: &MapSource, mode: GameMode, version: Version, mods: u32) -> Result<f64, CalcError> {
+        let map = beatmap.refx()?;
+        let stars = match (mode, version) {
+            (GameMode::Osu, Version::Vanilla) => map.pp().mods(mods).calculate().stars(),
+            (GameMode::Osu, Version::Relax) => refx_pp_rs::osu_2019::OsuPP::new(&map).mods(mods).calculate().difficulty.stars,
+            (GameMode::Osu, Version::ScoreV2) => refx_pp_rs::osu_2019_2::FxPP::new_from_map(&map).mods(mods).calculate().difficulty.stars,
+            (mode, _) => map.pp().mode(mode.into()).mods(mods).calculate().stars(),
+        };
+        Ok(stars)
+    }
+
+    fn calculate(
+        &self,
+        beatmap: &MapSource,
+        score: &PlayerScore,
+        mode: GameMode,
+        version: Version,
+        rx: bool,
+    ) -> Result<PpOutput, CalcError> {
+        let map = beatmap.refx()?;
+
+        if mode != GameMode::Osu {
+            return Ok(calculate_other_mode!(map, score, mode));
+        }
+
+        let output = match version {
+            Version::Vanilla => {
+                let mut calc = map.pp()
+                    .mods(score.mods)
+                    .combo(score.max_combo)
+                    .accuracy(score.acc)
+                    .n300(score.n300)
+                    .n100(score.n100)
+                    .n50(score.n50)
+                    .n_misses(score.nmiss);
+                if self.cheats {
+                    calc = calc
+                        .ac(score.aim_value)
+                        .arc(score.ar_value)
+                        .hdr(score.hdr != 0);
+                }
+                let result = calc.calculate();
+                PpOutput { pp: result.pp(), stars: result.stars(), mods: score.mods }
+            },
+
+            Version::Relax => {
+                let mut calc = refx_pp_rs::osu_2019::OsuPP::new(&map)
+                    .mods(score.mods)
+                    .combo(score.max_combo)
+                    .accuracy(score.acc as f32)
+                    .n300(score.n300)
+                    .n100(score.n100)
+                    .n50(score.n50)
+                    .misses(score.nmiss);
+                if self.cheats {
+                    calc = calc
+                        .ac(score.aim_value)
+                        .arc(score.ar_value)
+                        .tw(score.twval as usize)
+                        .cs(score.cs != 0);
+                }
+                let result = calc.calculate();
+                PpOutput { pp: result.pp, stars: result.difficulty.stars, mods: score.mods }
+            },
+
+            // same formula with and without cv
+            Version::ScoreV2 => {
+                let mods = scorev2_mods(score.mods, rx);
+                let result = refx_pp_rs::osu_2019_2::FxPP::new_from_map(&map)
+                    .mods(mods)
+                    .combo(score.max_combo)
+                    .accuracy(score.acc as f32)
+                    .n300(score.n300)
+                    .n100(score.n100)
+                    .n50(score.n50)
+                    .misses(score.nmiss)
+                    .calculate();
+                PpOutput { pp: result.pp, stars: result.difficulty.stars, mods }
+            },
+        };
+
+        Ok(output)
+    }
+}
+
+/// if-servers-legit, no cheat values at all
+pub struct LegitEngine;
+
+impl PpEngine for LegitEngine {
+    fn name(&self) -> &'static str {
+        "legit"
+    }
+
+    fn description(&self) -> &'static str {
+        "if-servers-legit, no cheat values"
+    }
+
+    fn cheats(&self) -> bool {
+        false
+    }
+
+    fn revision(&self) -> &'static str {
+        IF_SERVERS_LEGIT_REVISION
+    }
+
+    fn map_kind(&self) -> MapKind {
+        MapKind::Legit
+    }
+
+    fn parse(&self, beatmap: &MapSource) -> Result<MapInfo, CalcError> {
+        let map = beatmap.legit()?;
+        Ok(MapInfo { hit_objects: map.hit_objects.len(), mode: map.mode.into() })
+    }
+
+    fn difficulty(&self, beatmap: &MapSource, mode: GameMode, version: Version, mods: u32) -> Result<f64, CalcError> {
+        let map = beatmap.legit()?;
+        let stars = match (mode, version) {
+            (GameMode::Osu, Version::Vanilla) => map.pp().mods(mods).calculate().stars(),
+            (GameMode::Osu, Version::Relax) => if_servers_legit::osu_2019::OsuPP::new(&map).mods(mods).calculate().difficulty.stars,
+            (GameMode::Osu, Version::ScoreV2) => if_servers_legit::osu_2019_scorev2::FxPP::new_from_map(&map).mods(mods).calculate().difficulty.stars,
+            (mode, _) => map.pp().mode(mode.into()).mods(mods).calculate().stars(),
+        };
+        Ok(stars)
+    }
+
+    fn calculate(
+        &self,
+        beatmap: &MapSource,
+        score: &PlayerScore,
+        mode: GameMode,
+        version: Version,
+        rx: bool,
+    ) -> Result<PpOutput, CalcError> {
+        let map = beatmap.legit()?;
+
+        if mode != GameMode::Osu {
+            return Ok(calculate_other_mode!(map, score, mode));
+        }
+
+        let output = match version {
+            Version::Vanilla => {
+                let result = map.pp()
+                    .mods(score.mods)
+                    .combo(score.max_combo)
+                    .accuracy(score.acc)
+                    .n300(score.n300)
+                    .n100(score.n100)
+                    .n50(score.n50)
+                    .n_misses(score.nmiss)
+                    .calculate();
+                PpOutput { pp: result.pp(), stars: result.stars(), mods: score.mods }
+            },
+
+            Version::Relax => {
+                let result = if_servers_legit::osu_2019::OsuPP::new(&map)
+                    .mods(score.mods)
+                    .combo(score.max_combo)
+                    .accuracy(score.acc as f32)
+                    .n300(score.n300)
+                    .n100(score.n100)
+                    .n50(score.n50)
+                    .misses(score.nmiss)
+                    .calculate();
+                PpOutput { pp: result.pp, stars: result.difficulty.stars, mods: score.mods }
+            },
+
+            Version::ScoreV2 => {
+                let mods = scorev2_mods(score.mods, rx);
+                let result = if_servers_legit::osu_2019_scorev2::FxPP::new_from_map(&map)
+                    .mods(mods)
+                    .combo(score.max_combo)
+                    .accuracy(score.acc as f32)
+                    .n300(score.n300)
+                    .n100(score.n100)
+                    .n50(score.n50)
+                    .misses(score.nmiss)
+                    .calculate();
+                PpOutput { pp: result.pp, stars: result.difficulty.stars, mods }
+            },
+        };
+
+        Ok(output)
+    }
+}
+
+/// live-pp, what the server runs right now, always with cheat values
+pub struct LiveEngine;
+
+impl PpEngine for LiveEngine {
+    fn name(&self) -> &'static str {
+        "live"
+    }
+
+    fn description(&self) -> &'static str {
+        "live-pp, what the server runs right now"
+    }
+
+    fn cheats(&self) -> bool {
+        true
+    }
+
+    fn revision(&self) -> &'static str {
+        LIVE_PP_REVISION
+    }
+
+    fn map_kind(&self) -> MapKind {
+        MapKind::Live
+    }
+
+    fn parse(&self, beatmap: &MapSource) -> Result<MapInfo, CalcError> {
+        let map = beatmap.live()?;
+        Ok(MapInfo { hit_objects: map.hit_objects.len(), mode: map.mode.into() })
+    }
+
+    fn difficulty(&self, beatmap: &MapSource, mode: GameMode, version: Version, mods: u32) -> Result<f64, CalcError> {
+        let map = beatmap.live()?;
+        let stars = match (mode, version) {
+            (GameMode::Osu, Version::Vanilla) => map.pp().mods(mods).calculate().stars(),
+            (GameMode::Osu, Version::Relax) => live_pp::osu_2019::OsuPP::new(&map).mods(mods).calculate().difficulty.stars,
+            (GameMode::Osu, Version::ScoreV2) => live_pp::osu_2019_2::FxPP::new_from_map(&map).mods(mods).calculate().difficulty.stars,
+            (mode, _) => map.pp().mode(mode.into()).mods(mods).calculate().stars(),
+        };
+        Ok(stars)
+    }
+
+    fn calculate(
+        &self,
+        beatmap: &MapSource,
+        score: &PlayerScore,
+        mode: GameMode,
+        version: Version,
+        rx: bool,
+    ) -> Result<PpOutput, CalcError> {
+        let map = beatmap.live()?;
+
+        if mode != GameMode::Osu {
+            return Ok(calculate_other_mode!(map, score, mode));
+        }
+
+        let output = match version {
+            Version::Vanilla => {
+                let result = map.pp()
+                    .mods(score.mods)
+                    .combo(score.max_combo)
+                    .accuracy(score.acc)
+                    .n300(score.n300)
+                    .n100(score.n100)
+                    .n50(score.n50)
+                    .n_misses(score.nmiss)
+                    .ac(score.aim_value)
+                    .arc(score.ar_value)
+                    .hdr(score.hdr != 0)
+                    .calculate();
+                PpOutput { pp: result.pp(), stars: result.stars(), mods: score.mods }
+            },
+
+            Version::Relax => {
+                let result = live_pp::osu_2019::OsuPP::new(&map)
+                    .mods(score.mods)
+                    .combo(score.max_combo)
+                    .accuracy(score.acc as f32)
+                    .n300(score.n300)
+                    .n100(score.n100)
+                    .n50(score.n50)
+                    .misses(score.nmiss)
+                    .ac(score.aim_value)
+                    .arc(score.ar_value)
+                    .tw(score.twval as usize)
+                    .cs(score.cs != 0)
+                    .calculate();
+                PpOutput { pp: result.pp, stars: result.difficulty.stars, mods: score.mods }
+            },
+
+            Version::ScoreV2 => {
+                let mods = scorev2_mods(score.mods, rx);
+                let result = live_pp::osu_2019_2::FxPP::new_from_map(&map)
+                    .mods(mods)
+                    .combo(score.max_combo)
+                    .accuracy(score.acc as f32)
+                    .n300(score.n300)
+                    .n100(score.n100)
+                    .n50(score.n50)
+                    .misses(score.nmiss)
+                    .calculate();
+                PpOutput { pp: result.pp, stars: result.difficulty.stars, mods }
+            },
+        };
+
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    use crate::calculate::engine::registry;
+    use crate::calculate::params::NamedParam;
+    use crate::models::BeatmapInfo;
+
+    const FIXTURE: &[u8] = include_bytes!("fixtures/std.osu");
+
+    fn expected_path() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/calculate/fixtures/std.expected")
+    }
+
+    fn score(mods: u32) -> PlayerScore {
+        PlayerScore {
+            score: 1_000_000,
+            pp: 0.0,
+            acc: 97.5,
+            max_combo: 15,
+            mods,
+            n300: 14,
+            n100: 1,
+            n50: 0,
+            nmiss: 0,
+            ngeki: 0,
+            nkatu: 0,
+            aim_value: 1,
+            ar_value: 9.5,
+            cs: 1,
+            twval: 1.0,
+            hdr: 1,
+            beatmap: BeatmapInfo { id: 0, md5: String::new() },
+        }
+    }
+
+    /// one line per branch, version, mods and rx, with the pp, stars and mods it gave
+    fn outputs(map: &MapSource) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        for engine in registry().all() {
+            for &version in engine.versions() {
+                for mods in [0, 8, 64, 72] {
+                    for rx in [false, true] {
+                        let output = engine.calculate(map, &score(mods), GameMode::Osu, version, rx).unwrap();
+                        lines.push(format!(
+                            "{} {} {} {} {:.4} {:.4} {}",
+                            engine.name(), version.name(), mods, rx, output.pp, output.stars, output.mods
+                        ));
+                    }
+                }
+            }
+        }
+
+        lines
+    }
+
+    /// fixtures/std.expected pins the pp and stars every branch gives for the fixture,
+    /// `RECORD_FIXTURES=1 cargo test` rewrites it, only do that for an intended change
+    #[test]
+    fn engines_match_the_recorded_output() {
+        // legit and live block on their own async parser, which needs a runtime to enter
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+
+        let map = MapSource::uploaded(FIXTURE.to_vec());
+        let actual = outputs(&map);
+
+        if env::var_os("RECORD_FIXTURES").is_some() {
+            fs::write(expected_path(), actual.join("\n") + "\n").unwrap();
+            return;
+        }
+
+        let expected = fs::read_to_string(expected_path())
+            .expect("fixtures/std.expected is missing, record it with RECORD_FIXTURES=1");
+        let expected: Vec<&str> = expected.lines().collect();
+
+        assert_eq!(actual.len(), expected.len(), "branches or versions changed, re-record the fixture");
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn every_engine_parses_the_fixture() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+
+        let map = MapSource::uploaded(FIXTURE.to_vec());
+
+        for engine in registry().all() {
+            let info = engine.parse(&map).unwrap();
+            assert_eq!(info.hit_objects, 15, "{}", engine.name());
+            assert_eq!(info.mode, GameMode::Osu, "{}", engine.name());
+        }
+    }
+
+    #[test]
+    fn difficulty_goes_up_with_hard_mods() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let _guard = runtime.enter();
+
+        let map = MapSource::uploaded(FIXTURE.to_vec());
+
+        for engine in registry().all() {
+            for &version in engine.versions() {
+                let nomod = engine.difficulty(&map, GameMode::Osu, version, 0).unwrap();
+                let doubletime = engine.difficulty(&map, GameMode::Osu, version, 64).unwrap();
+                assert!(nomod > 0.0, "{} {}", engine.name(), version.name());
+                assert!(doubletime > nomod, "{} {}", engine.name(), version.name());
+            }
+        }
+    }
+}
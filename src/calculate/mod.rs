@@ -1,6 +1,26 @@
 mod cache;
 mod api;
 mod utils;
+mod totals;
+mod diff;
+mod pool;
+mod engines;
 
 pub mod calculate;
-pub use api::calculate_pp_now;
\ No newline at end of file
+pub mod parsed;
+pub mod params;
+pub mod engine;
+pub use api::{
+    calculate_batch,
+    calculate_beatmap_difficulty,
+    calculate_pp_now,
+    calculate_score,
+    calculate_uploaded_beatmap,
+    compare_pp_now,
+    stream_pp_now,
+    CalcContext,
+    LeaderboardOptions,
+};
+pub use cache::{Cache, CacheConfig};
+pub use totals::project_totals;
+pub use diff::diff_runs;
\ No newline at end of file
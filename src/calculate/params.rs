@@ -0,0 +1,260 @@
+/// the mode and version a calculation is asked for, branches are resolved by `engine::registry`
+/// both are accepted by name or by the number the api used before names existed
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+/// json bodies can send either the name or the number, query strings are always strings
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum RawParam {
+    Number(u8),
+    Name(String),
+}
+
+pub trait NamedParam: Copy + Sized + 'static {
+    /// used in error messages, e.g. "mode"
+    const KIND: &'static str;
+    const ALL: &'static [Self];
+
+    fn name(self) -> &'static str;
+    fn number(self) -> u8;
+
+    /// "live (0), cv (1), ..." for error messages
+    fn allowed() -> String {
+        Self::ALL.iter()
+            .map(|value| format!("{} ({})", value.name(), value.number()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        let found = match value.parse::<u8>() {
+            Ok(number) => Self::ALL.iter().find(|v| v.number() == number),
+            Err(_) => Self::ALL.iter().find(|v| v.name().eq_ignore_ascii_case(value)),
+        };
+
+        found.copied().ok_or_else(|| format!(
+            "Invalid {} '{}'. Allowed values: {}.",
+            Self::KIND, value, Self::allowed()
+        ))
+    }
+}
+
+/// bancho.py game modes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawParam", into = "String")]
+pub enum Mode {
+    VanillaStd,
+    VanillaTaiko,
+    VanillaCatch,
+    VanillaMania,
+    RelaxStd,
+    RelaxTaiko,
+    RelaxCatch,
+    RelaxMania,
+    AutopilotStd,
+}
+
+impl NamedParam for Mode {
+    const KIND: &'static str = "mode";
+    const ALL: &'static [Self] = &[
+        Mode::VanillaStd, Mode::VanillaTaiko, Mode::VanillaCatch, Mode::VanillaMania,
+        Mode::RelaxStd, Mode::RelaxTaiko, Mode::RelaxCatch, Mode::RelaxMania,
+        Mode::AutopilotStd,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Mode::VanillaStd => "vn!std",
+            Mode::VanillaTaiko => "vn!taiko",
+            Mode::VanillaCatch => "vn!catch",
+            Mode::VanillaMania => "vn!mania",
+            Mode::RelaxStd => "rx!std",
+            Mode::RelaxTaiko => "rx!taiko",
+            Mode::RelaxCatch => "rx!catch",
+            Mode::RelaxMania => "rx!mania",
+            Mode::AutopilotStd => "ap!std",
+        }
+    }
+
+    /// what bancho.py expects in `mode=`
+    fn number(self) -> u8 {
+        match self {
+            Mode::VanillaStd => 0,
+            Mode::VanillaTaiko => 1,
+            Mode::VanillaCatch => 2,
+            Mode::VanillaMania => 3,
+            Mode::RelaxStd => 4,
+            Mode::RelaxTaiko => 5,
+            Mode::RelaxCatch => 6,
+            Mode::RelaxMania => 7,
+            Mode::AutopilotStd => 8,
+        }
+    }
+}
+
+impl Mode {
+    /// the ruleset scores of this mode are played in, relax and autopilot dont change it
+    pub fn game_mode(self) -> GameMode {
+        match self {
+            Mode::VanillaStd | Mode::RelaxStd | Mode::AutopilotStd => GameMode::Osu,
+            Mode::VanillaTaiko | Mode::RelaxTaiko => GameMode::Taiko,
+            Mode::VanillaCatch | Mode::RelaxCatch => GameMode::Catch,
+            Mode::VanillaMania | Mode::RelaxMania => GameMode::Mania,
+        }
+    }
+}
+
+/// the ruleset a score is calculated with, every engine crate has its own copy of this
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameMode {
+    Osu,
+    Taiko,
+    Catch,
+    Mania,
+}
+
+impl GameMode {
+    /// relax and scorev2 are std formulas, other modes only have their vanilla one
+    pub fn check_version(self, version: Version) -> Result<(), String> {
+        if self != GameMode::Osu && version != Version::Vanilla {
+            return Err(format!(
+                "Version '{}' only exists for std, {} can only be calculated with '{}'.",
+                version, self, Version::Vanilla
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for GameMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            GameMode::Osu => "std",
+            GameMode::Taiko => "taiko",
+            GameMode::Catch => "catch",
+            GameMode::Mania => "mania",
+        })
+    }
+}
+
+/// which pp formula of a branch is used
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "RawParam", into = "String")]
+pub enum Version {
+    Vanilla,
+    Relax,
+    ScoreV2,
+}
+
+impl NamedParam for Version {
+    const KIND: &'static str = "version";
+    const ALL: &'static [Self] = &[Version::Vanilla, Version::Relax, Version::ScoreV2];
+
+    fn name(self) -> &'static str {
+        match self {
+            Version::Vanilla => "vanilla",
+            Version::Relax => "relax",
+            Version::ScoreV2 => "scorev2",
+        }
+    }
+
+    fn number(self) -> u8 {
+        match self {
+            Version::Vanilla => 0,
+            Version::Relax => 1,
+            Version::ScoreV2 => 2,
+        }
+    }
+}
+
+macro_rules! impl_conversions {
+    ($($param:ty),*) => {$(
+        impl FromStr for $param {
+            type Err = String;
+
+            fn from_str(value: &str) -> Result<Self, Self::Err> {
+                <$param as NamedParam>::parse(value)
+            }
+        }
+
+        impl TryFrom<RawParam> for $param {
+            type Error = String;
+
+            fn try_from(value: RawParam) -> Result<Self, Self::Error> {
+                match value {
+                    RawParam::Number(number) => number.to_string().parse(),
+                    RawParam::Name(name) => name.parse(),
+                }
+            }
+        }
+
+        impl From<$param> for String {
+            fn from(value: $param) -> Self {
+                value.name().to_string()
+            }
+        }
+
+        impl fmt::Display for $param {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(self.name())
+            }
+        }
+    )*};
+}
+
+impl_conversions!(Mode, Version);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_names_case_insensitively() {
+        assert_eq!(Mode::parse("rx!std"), Ok(Mode::RelaxStd));
+        assert_eq!(Mode::parse("AP!STD"), Ok(Mode::AutopilotStd));
+        assert_eq!(Version::parse(" ScoreV2 "), Ok(Version::ScoreV2));
+    }
+
+    #[test]
+    fn parses_old_numbers() {
+        assert_eq!(Mode::parse("4"), Ok(Mode::RelaxStd));
+        assert_eq!(Mode::parse("8"), Ok(Mode::AutopilotStd));
+        assert_eq!(Version::parse("1"), Ok(Version::Relax));
+    }
+
+    #[test]
+    fn lists_allowed_values_on_invalid_input() {
+        let err = Mode::parse("9").unwrap_err();
+        assert!(err.starts_with("Invalid mode '9'. Allowed values: vn!std (0), vn!taiko (1)"), "{}", err);
+
+        assert_eq!(
+            Version::parse("fast"),
+            Err("Invalid version 'fast'. Allowed values: vanilla (0), relax (1), scorev2 (2).".to_string()),
+        );
+        assert!(Version::parse("").is_err());
+        assert!(Version::parse("-1").is_err());
+    }
+
+    #[test]
+    fn json_accepts_names_and_numbers() {
+        assert_eq!(serde_json::from_str::<Version>("2").unwrap(), Version::ScoreV2);
+        assert_eq!(serde_json::from_str::<Version>("\"relax\"").unwrap(), Version::Relax);
+        assert!(serde_json::from_str::<Mode>("42").is_err());
+        assert_eq!(serde_json::to_string(&Mode::RelaxStd).unwrap(), "\"rx!std\"");
+    }
+
+    #[test]
+    fn only_std_has_other_versions() {
+        for version in Version::ALL {
+            assert!(GameMode::Osu.check_version(*version).is_ok());
+        }
+        assert!(GameMode::Taiko.check_version(Version::Vanilla).is_ok());
+        assert!(GameMode::Mania.check_version(Version::Relax).is_err());
+        assert!(GameMode::Catch.check_version(Version::ScoreV2).is_err());
+    }
+}
@@ -0,0 +1,329 @@
+/// parsed beatmaps kept in memory, one cache per engine crate since each
+/// crate has its own Beatmap type
+/// keyed by beatmap id and md5 so an updated map never hits an old parse
+
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+
+use tokio::runtime::Handle;
+
+use crate::beatmap::{BeatmapCache, BeatmapPin};
+use crate::error::CalcError;
+
+use refx_pp_rs::Beatmap;
+use if_servers_legit::Beatmap as ifLegitBeatmap;
+use live_pp::Beatmap as livePPBeatmap;
+
+// a parsed map takes a few times the size of its .osu file
+const PARSED_SIZE_FACTOR: u64 = 3;
+const DEFAULT_MAX_BYTES: u64 = 512 * 1024 * 1024;
+
+type ParsedKey = (u64, String);
+
+struct ParsedEntry<T> {
+    map: Arc<T>,
+    size: u64,
+    last_access: u64,
+}
+
+struct ParsedCacheInner<T> {
+    entries: HashMap<ParsedKey, ParsedEntry<T>>,
+    clock: u64,
+    total_bytes: u64,
+}
+
+/// least recently used maps are dropped once `max_bytes` is reached
+pub struct ParsedCache<T> {
+    inner: Mutex<ParsedCacheInner<T>>,
+    max_bytes: u64,
+}
+
+impl<T> ParsedCache<T> {
+    pub fn new(max_bytes: u64) -> Self {
+        Self {
+            inner: Mutex::new(ParsedCacheInner {
+                entries: HashMap::new(),
+                clock: 0,
+                total_bytes: 0,
+            }),
+            max_bytes,
+        }
+    }
+
+    pub fn get(&self, key: &ParsedKey) -> Option<Arc<T>> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clock += 1;
+        let clock = inner.clock;
+
+        inner.entries.get_mut(key).map(|entry| {
+            entry.last_access = clock;
+            entry.map.clone()
+        })
+    }
+
+    /// `size` is the size of the .osu file, the memory used is estimated from it
+    pub fn insert(&self, key: ParsedKey, size: u64, map: Arc<T>) {
+        let size = size * PARSED_SIZE_FACTOR;
+        // wouldnt fit even in an empty cache
+        if size > self.max_bytes {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.clock += 1;
+        let last_access = inner.clock;
+
+        if let Some(old) = inner.entries.insert(key, ParsedEntry { map, size, last_access }) {
+            inner.total_bytes -= old.size;
+        }
+        inner.total_bytes += size;
+
+        while inner.total_bytes > self.max_bytes {
+            let Some(oldest) = inner.entries.iter()
+                .min_by_key(|(_, entry)| entry.last_access)
+                .map(|(key, _)| key.clone()) else {
+                break;
+            };
+
+            if let Some(entry) = inner.entries.remove(&oldest) {
+                inner.total_bytes -= entry.size;
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ParsedBeatmaps {
+    refx: Arc<ParsedCache<Beatmap>>,
+    legit: Arc<ParsedCache<ifLegitBeatmap>>,
+    live: Arc<ParsedCache<livePPBeatmap>>,
+}
+
+impl ParsedBeatmaps {
+    /// `PARSED_CACHE_MAX_BYTES` is split evenly between the engine crates
+    pub fn from_env() -> Self {
+        let max_bytes = env::var("PARSED_CACHE_MAX_BYTES")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(DEFAULT_MAX_BYTES);
+        let per_engine = max_bytes / 3;
+
+        Self {
+            refx: Arc::new(ParsedCache::new(per_engine)),
+            legit: Arc::new(ParsedCache::new(per_engine)),
+            live: Arc::new(ParsedCache::new(per_engine)),
+        }
+    }
+}
+
+/// the refx-pp crates from Cargo.toml, each has its own Beatmap type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapKind {
+    /// refx-pp-rs, main with and without cv
+    Refx,
+    /// if-servers-legit
+    Legit,
+    /// live-pp
+    Live,
+}
+
+enum Origin {
+    Cache(BeatmapCache),
+    /// uploaded files are never cached, they have no id to key them by
+    Upload,
+}
+
+/// where a calculation gets its map from
+/// `prepare` runs on the async side and only reads (or downloads) the .osu file
+/// if the engine's parsed cache doesnt have it, the actual parsing happens
+/// later on the calculation pool
+pub struct MapSource {
+    beatmap_id: u64,
+    md5: Option<String>,
+    origin: Origin,
+    /// the raw .osu file, never decoded before parsing
+    contents: Option<Vec<u8>>,
+    /// held from `prepare` until this is dropped, after the calculation
+    pin: Option<BeatmapPin>,
+    refx: Mutex<Option<Arc<Beatmap>>>,
+    legit: Mutex<Option<Arc<ifLegitBeatmap>>>,
+    live: Mutex<Option<Arc<livePPBeatmap>>>,
+}
+
+impl MapSource {
+    fn new(beatmap_id: u64, md5: Option<String>, origin: Origin, contents: Option<Vec<u8>>) -> Self {
+        Self {
+            beatmap_id,
+            md5,
+            origin,
+            contents,
+            pin: None,
+            refx: Mutex::new(None),
+            legit: Mutex::new(None),
+            live: Mutex::new(None),
+        }
+    }
+
+    pub fn cached(beatmap_cache: &BeatmapCache, beatmap_id: u64, md5: &str) -> Self {
+        let md5 = Some(md5.to_string()).filter(|md5| !md5.is_empty());
+        Self::new(beatmap_id, md5, Origin::Cache(beatmap_cache.clone()), None)
+    }
+
+    pub fn uploaded(contents: Vec<u8>) -> Self {
+        Self::new(0, None, Origin::Upload, Some(contents))
+    }
+
+    pub fn beatmap_id(&self) -> u64 {
+        self.beatmap_id
+    }
+
+    fn caches(&self) -> Option<&ParsedBeatmaps> {
+        match &self.origin {
+            Origin::Cache(beatmap_cache) => Some(beatmap_cache.parsed()),
+            Origin::Upload => None,
+        }
+    }
+
+    fn key(&self) -> Option<ParsedKey> {
+        let Origin::Cache(beatmap_cache) = &self.origin else {
+            return None;
+        };
+
+        let md5 = self.md5.clone().or_else(|| beatmap_cache.cached_md5(self.beatmap_id))?;
+        Some((self.beatmap_id, md5))
+    }
+
+    /// takes the map from the parsed cache if it is there, true if the slot is filled
+    fn fill_from_cache<T>(&self, slot: &Mutex<Option<Arc<T>>>, cache: Option<&ParsedCache<T>>) -> bool {
+        let mut slot = slot.lock().unwrap();
+        if slot.is_some() {
+            return true;
+        }
+
+        let (Some(cache), Some(key)) = (cache, self.key()) else {
+            return false;
+        };
+        let Some(map) = cache.get(&key) else {
+            return false;
+        };
+
+        if let Origin::Cache(beatmap_cache) = &self.origin {
+            beatmap_cache.record_hit(self.beatmap_id);
+        }
+        *slot = Some(map);
+        true
+    }
+
+    /// makes sure a map of `kind` can be loaded without any more io
+    pub async fn prepare(&mut self, kind: MapKind) -> Result<(), CalcError> {
+        let parsed = match kind {
+            MapKind::Refx => self.fill_from_cache(&self.refx, self.caches().map(|c| c.refx.as_ref())),
+            MapKind::Legit => self.fill_from_cache(&self.legit, self.caches().map(|c| c.legit.as_ref())),
+            MapKind::Live => self.fill_from_cache(&self.live, self.caches().map(|c| c.live.as_ref())),
+        };
+
+        if parsed || self.contents.is_some() {
+            return Ok(());
+        }
+
+        if let Origin::Cache(beatmap_cache) = &self.origin {
+            self.pin = Some(beatmap_cache.pin(self.beatmap_id));
+            let contents = beatmap_cache.get_or_download_beatmap(self.beatmap_id, self.md5.as_deref()).await?;
+            self.contents = Some(contents);
+        }
+
+        Ok(())
+    }
+
+    fn load<T, E>(
+        &self,
+        slot: &Mutex<Option<Arc<T>>>,
+        cache: Option<&ParsedCache<T>>,
+        parse: impl FnOnce(&[u8]) -> Result<T, E>,
+    ) -> Result<Arc<T>, CalcError>
+    where
+        E: Display,
+    {
+        let mut slot = slot.lock().unwrap();
+        if let Some(map) = slot.as_ref() {
+            return Ok(map.clone());
+        }
+
+        let contents = self.contents.as_deref()
+            .ok_or_else(|| CalcError::Engine("Beatmap was not prepared!".to_string()))?;
+        let map = Arc::new(parse(contents).map_err(|e| CalcError::Parse(e.to_string()))?);
+
+        if let (Some(cache), Some(key)) = (cache, self.key()) {
+            cache.insert(key, contents.len() as u64, map.clone());
+        }
+
+        *slot = Some(map.clone());
+        Ok(map)
+    }
+
+    pub fn refx(&self) -> Result<Arc<Beatmap>, CalcError> {
+        self.load(&self.refx, self.caches().map(|c| c.refx.as_ref()), Beatmap::from_bytes)
+    }
+
+    /// the async engine crates are driven to completion right here,
+    /// so this must only be called from the calculation pool
+    pub fn legit(&self) -> Result<Arc<ifLegitBeatmap>, CalcError> {
+        self.load(&self.legit, self.caches().map(|c| c.legit.as_ref()), |bytes| {
+            Handle::current().block_on(ifLegitBeatmap::from_bytes(bytes))
+        })
+    }
+
+    pub fn live(&self) -> Result<Arc<livePPBeatmap>, CalcError> {
+        self.load(&self.live, self.caches().map(|c| c.live.as_ref()), |bytes| {
+            Handle::current().block_on(livePPBeatmap::from_bytes(bytes))
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(beatmap_id: u64) -> ParsedKey {
+        (beatmap_id, format!("md5-{}", beatmap_id))
+    }
+
+    #[test]
+    fn drops_least_recently_used_maps() {
+        // room for two 10 byte files, each counted three times
+        let cache = ParsedCache::new(70);
+        cache.insert(key(1), 10, Arc::new(1));
+        cache.insert(key(2), 10, Arc::new(2));
+        assert_eq!(cache.get(&key(1)).as_deref(), Some(&1));
+
+        cache.insert(key(3), 10, Arc::new(3));
+
+        assert!(cache.get(&key(2)).is_none());
+        assert_eq!(cache.get(&key(1)).as_deref(), Some(&1));
+        assert_eq!(cache.get(&key(3)).as_deref(), Some(&3));
+        assert_eq!(cache.inner.lock().unwrap().total_bytes, 60);
+    }
+
+    #[test]
+    fn replacing_a_map_keeps_the_size_right() {
+        let cache = ParsedCache::new(100);
+        cache.insert(key(1), 10, Arc::new(1));
+        cache.insert(key(1), 20, Arc::new(2));
+
+        assert_eq!(cache.get(&key(1)).as_deref(), Some(&2));
+        assert_eq!(cache.inner.lock().unwrap().total_bytes, 60);
+    }
+
+    #[test]
+    fn skips_maps_bigger_than_the_cache() {
+        let cache = ParsedCache::new(70);
+        cache.insert(key(1), 10, Arc::new(1));
+        cache.insert(key(2), 24, Arc::new(2));
+
+        assert!(cache.get(&key(2)).is_none());
+        assert_eq!(cache.get(&key(1)).as_deref(), Some(&1));
+    }
+}
@@ -0,0 +1,43 @@
+/// the pp calculations themselves are cpu bound and would block tokio's workers,
+/// so they run on the blocking pool with at most `CALC_THREADS` at a time
+
+use std::env;
+use std::sync::OnceLock;
+use std::thread;
+
+use tokio::sync::Semaphore;
+
+use crate::error::CalcError;
+
+static PERMITS: OnceLock<Semaphore> = OnceLock::new();
+
+/// `CALC_THREADS`, defaults to one per core
+pub fn threads() -> usize {
+    env::var("CALC_THREADS")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or_else(|| thread::available_parallelism().map(|n| n.get()).unwrap_or(1))
+}
+
+fn permits() -> &'static Semaphore {
+    PERMITS.get_or_init(|| {
+        let threads = threads();
+        println!("Calculating on up to {} threads", threads);
+        Semaphore::new(threads)
+    })
+}
+
+/// runs `f` on the calculation pool, waiting for a free thread first
+pub async fn run<F, T>(f: F) -> Result<T, CalcError>
+where
+    F: FnOnce() -> Result<T, CalcError> + Send + 'static,
+    T: Send + 'static,
+{
+    let _permit = permits().acquire().await
+        .map_err(|e| CalcError::Engine(e.to_string()))?;
+
+    // a panic inside the engine crates only fails this one calculation
+    tokio::task::spawn_blocking(f).await
+        .map_err(|e| CalcError::Engine(e.to_string()))?
+}
@@ -0,0 +1,153 @@
+/// turns recalculated scores into player totals, the same way bancho.py does it
+/// so we can see who moves up or down with a rework
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::models::{LeaderboardStanding, PlayerProjection, PPCalculationResult};
+use crate::calculate::utils::round;
+
+// bancho.py only weights the top 100 scores
+const MAX_WEIGHTED_SCORES: usize = 100;
+
+/// 0.95^n weighting over the best scores plus bancho.py's bonus pp
+pub fn total_pp(mut pps: Vec<f64>) -> f64 {
+    pps.sort_by(|a, b| b.partial_cmp(a).unwrap_or(Ordering::Equal));
+
+    let weighted: f64 = pps.iter()
+        .take(MAX_WEIGHTED_SCORES)
+        .enumerate()
+        .map(|(i, pp)| pp * 0.95f64.powi(i as i32))
+        .sum();
+    let bonus = 416.6667 * (1.0 - 0.9994f64.powi(pps.len() as i32));
+
+    weighted + bonus
+}
+
+fn ranks_by<F: Fn(&PlayerProjection) -> f64>(projections: &[PlayerProjection], key: F) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..projections.len()).collect();
+    order.sort_by(|&a, &b| {
+        key(&projections[b]).partial_cmp(&key(&projections[a])).unwrap_or(Ordering::Equal)
+    });
+
+    let mut ranks = vec![0; projections.len()];
+    for (rank, index) in order.into_iter().enumerate() {
+        ranks[index] = rank + 1;
+    }
+    ranks
+}
+
+/// old and new totals per player, sorted by the new leaderboard position
+/// the old side is the player's real pp and rank, only the fetched scores are recalculated
+/// so the new total is the real one plus how much those scores changed
+/// players outside the fetched page are assumed not to move
+pub fn project_totals(
+    results: &HashMap<String, Vec<PPCalculationResult>>,
+    standings: &HashMap<String, LeaderboardStanding>,
+) -> Vec<PlayerProjection> {
+    let mut projections: Vec<PlayerProjection> = results.iter()
+        .filter_map(|(player_name, scores)| {
+            let standing = standings.get(player_name)?;
+            let fetched_old = total_pp(scores.iter().map(|s| s.original_pp).collect());
+            let fetched_new = total_pp(scores.iter().map(|s| s.recalculated_pp).collect());
+
+            let old_total = round(standing.pp, 2);
+            let new_total = round(standing.pp + fetched_new - fetched_old, 2);
+
+            Some(PlayerProjection {
+                player_name: player_name.clone(),
+                old_total,
+                new_total,
+                difference: round(new_total - old_total, 2),
+                old_rank: standing.rank,
+                new_rank: 0,
+                rank_change: 0,
+            })
+        })
+        .collect();
+
+    // the page starts at the best rank in it, new ranks are handed out from there
+    let first_rank = projections.iter().map(|p| p.old_rank).min().unwrap_or(1);
+    let new_ranks = ranks_by(&projections, |p| p.new_total);
+
+    for (i, projection) in projections.iter_mut().enumerate() {
+        projection.new_rank = first_rank + new_ranks[i] - 1;
+        // positive means the player moved up
+        projection.rank_change = projection.old_rank as i64 - projection.new_rank as i64;
+    }
+
+    projections.sort_by_key(|p| p.new_rank);
+    projections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(original_pp: f64, recalculated_pp: f64) -> PPCalculationResult {
+        PPCalculationResult {
+            stars: 5.0,
+            beatmap_id: 1,
+            original_pp,
+            recalculated_pp,
+            difference: recalculated_pp - original_pp,
+            mods: 0,
+            version: 0,
+            engine_revision: String::new(),
+        }
+    }
+
+    fn bonus(n: i32) -> f64 {
+        416.6667 * (1.0 - 0.9994f64.powi(n))
+    }
+
+    #[test]
+    fn no_scores_is_zero() {
+        assert_eq!(total_pp(Vec::new()), 0.0);
+    }
+
+    #[test]
+    fn weights_best_scores_first() {
+        let total = total_pp(vec![100.0, 200.0]);
+        assert!((total - (200.0 + 100.0 * 0.95 + bonus(2))).abs() < 1e-9);
+        assert_eq!(total, total_pp(vec![200.0, 100.0]));
+    }
+
+    #[test]
+    fn only_top_100_are_weighted() {
+        let total = total_pp(vec![100.0; 150]);
+        let weighted = 100.0 * (1.0 - 0.95f64.powi(100)) / 0.05;
+        assert!((total - (weighted + bonus(150))).abs() < 1e-6);
+    }
+
+    #[test]
+    fn projects_from_real_standings() {
+        let results = HashMap::from([
+            ("a".to_string(), vec![result(100.0, 100.0)]),
+            ("b".to_string(), vec![result(100.0, 300.0)]),
+        ]);
+        let standings = HashMap::from([
+            ("a".to_string(), LeaderboardStanding { pp: 5000.0, rank: 11 }),
+            ("b".to_string(), LeaderboardStanding { pp: 4900.0, rank: 12 }),
+        ]);
+
+        let projections = project_totals(&results, &standings);
+
+        assert_eq!(projections[0].player_name, "b");
+        assert_eq!(projections[0].old_total, 4900.0);
+        assert_eq!(projections[0].new_total, 5100.0);
+        assert_eq!(projections[0].new_rank, 11);
+        assert_eq!(projections[0].rank_change, 1);
+
+        assert_eq!(projections[1].player_name, "a");
+        assert_eq!(projections[1].difference, 0.0);
+        assert_eq!(projections[1].new_rank, 12);
+        assert_eq!(projections[1].rank_change, -1);
+    }
+
+    #[test]
+    fn players_without_a_standing_are_left_out() {
+        let results = HashMap::from([("a".to_string(), vec![result(100.0, 120.0)])]);
+        assert!(project_totals(&results, &HashMap::new()).is_empty());
+    }
+}
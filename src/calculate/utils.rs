@@ -1,4 +1,61 @@
 pub fn round(x: f64, decimals: u32) -> f64 {
     let y = 10i32.pow(decimals) as f64;
     (x * y).round() / y
-}
\ No newline at end of file
+}
+
+/// spreads an accuracy (in percent) over 300s, 100s and 50s
+/// used when we only know the acc, e.g. for uploaded maps
+pub fn hitresults_from_acc(n_objects: usize, acc: f64, misses: usize) -> (usize, usize, usize) {
+    let remaining = n_objects.saturating_sub(misses) as f64;
+    // in units of 50s, a 300 is worth 6 and a 100 is worth 2
+    let target = (acc / 100.0).clamp(0.0, 1.0) * 6.0 * n_objects as f64;
+
+    if target >= 2.0 * remaining {
+        let n300 = ((target - 2.0 * remaining) / 4.0).round().clamp(0.0, remaining);
+        (n300 as usize, (remaining - n300) as usize, 0)
+    } else {
+        let n100 = (target - remaining).round().clamp(0.0, remaining);
+        (0, n100 as usize, (remaining - n100) as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn accuracy((n300, n100, n50): (usize, usize, usize), misses: usize) -> f64 {
+        let total = (n300 + n100 + n50 + misses) as f64;
+        100.0 * (6 * n300 + 2 * n100 + n50) as f64 / (6.0 * total)
+    }
+
+    #[test]
+    fn rounds_to_decimals() {
+        assert_eq!(round(1.23456, 2), 1.23);
+        assert_eq!(round(1.235, 0), 1.0);
+    }
+
+    #[test]
+    fn full_acc_is_all_300s() {
+        assert_eq!(hitresults_from_acc(100, 100.0, 0), (100, 0, 0));
+        assert_eq!(hitresults_from_acc(100, 120.0, 0), (100, 0, 0));
+    }
+
+    #[test]
+    fn low_acc_uses_50s() {
+        assert_eq!(hitresults_from_acc(100, 0.0, 0), (0, 0, 100));
+    }
+
+    #[test]
+    fn hits_add_up_and_match_the_acc() {
+        for &(acc, misses) in &[(99.0, 0), (95.0, 2), (80.0, 5), (40.0, 10)] {
+            let hits = hitresults_from_acc(500, acc, misses);
+            assert_eq!(hits.0 + hits.1 + hits.2, 500 - misses);
+            assert!((accuracy(hits, misses) - acc).abs() < 0.5, "{} {:?}", acc, hits);
+        }
+    }
+
+    #[test]
+    fn more_misses_than_objects() {
+        assert_eq!(hitresults_from_acc(10, 90.0, 20), (0, 0, 0));
+    }
+}
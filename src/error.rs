@@ -0,0 +1,158 @@
+/// errors shared by the calculation code and the http handlers
+/// every error has a stable `code` so clients dont have to match on messages
+
+use axum::extract::rejection::{JsonRejection, MultipartRejection, PathRejection};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use thiserror::Error;
+
+use crate::beatmap::BeatmapCacheError;
+use crate::http::HttpError;
+use crate::models::ErrorInfo;
+
+#[derive(Error, Debug)]
+pub enum CalcError {
+    #[error("Upstream request failed: {0}")]
+    Upstream(#[from] HttpError),
+    #[error("Upstream sent an invalid response: {0}")]
+    UpstreamResponse(String),
+    #[error(transparent)]
+    Beatmap(#[from] BeatmapCacheError),
+    #[error("Failed to parse beatmap: {0}")]
+    Parse(String),
+    #[error("PP calculation failed: {0}")]
+    Engine(String),
+    #[error("{0}")]
+    InvalidInput(String),
+    /// a task that panicked or was cancelled, only that item fails
+    #[error("Internal error: {0}")]
+    Internal(String),
+}
+
+// only the json bodies are read outside of `HttpClient::get`, so these are decoding errors
+impl From<reqwest::Error> for CalcError {
+    fn from(e: reqwest::Error) -> Self {
+        CalcError::UpstreamResponse(e.to_string())
+    }
+}
+
+impl From<serde_json::Error> for CalcError {
+    fn from(e: serde_json::Error) -> Self {
+        CalcError::UpstreamResponse(e.to_string())
+    }
+}
+
+fn beatmap_code(e: &BeatmapCacheError) -> &'static str {
+    match e {
+        BeatmapCacheError::InvalidBeatmap { .. } => "invalid_beatmap",
+        BeatmapCacheError::VersionMismatch { .. } => "beatmap_version_mismatch",
+        BeatmapCacheError::Timeout { .. } => "beatmap_timeout",
+        BeatmapCacheError::Shared(inner) => beatmap_code(inner),
+        _ => "beatmap_unavailable",
+    }
+}
+
+impl CalcError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            CalcError::Upstream(HttpError::Status { status, .. }) if status.as_u16() == 429 => "upstream_rate_limited",
+            CalcError::Upstream(_) => "upstream_error",
+            CalcError::UpstreamResponse(_) => "upstream_invalid_response",
+            CalcError::Beatmap(e) => beatmap_code(e),
+            CalcError::Parse(_) => "beatmap_parse_error",
+            CalcError::Engine(_) => "engine_error",
+            CalcError::InvalidInput(_) => "invalid_request",
+            CalcError::Internal(_) => "internal_error",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            CalcError::Upstream(_) | CalcError::UpstreamResponse(_) => StatusCode::BAD_GATEWAY,
+            CalcError::Beatmap(_) if self.code() == "beatmap_timeout" => StatusCode::GATEWAY_TIMEOUT,
+            CalcError::Beatmap(_) => StatusCode::BAD_GATEWAY,
+            CalcError::Parse(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            CalcError::Engine(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            CalcError::InvalidInput(_) => StatusCode::BAD_REQUEST,
+            CalcError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn info(&self) -> ErrorInfo {
+        ErrorInfo {
+            code: self.code().to_string(),
+            message: self.to_string(),
+        }
+    }
+}
+
+/// what handlers return on failure, sent as `{"code": ..., "message": ...}`
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "invalid_request", message)
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::FORBIDDEN, "forbidden", message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "not_found", message)
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::CONFLICT, "conflict", message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal_error", message)
+    }
+}
+
+impl From<CalcError> for ApiError {
+    fn from(e: CalcError) -> Self {
+        eprintln!("Error: {}", e);
+        Self::new(e.status(), e.code(), e.to_string())
+    }
+}
+
+// axum answers a body or path it cant extract in plain text, handlers take the extractor
+// as a `Result` and turn its rejection into the usual json error with `?`
+macro_rules! impl_from_rejection {
+    ($($rejection:ty),*) => {$(
+        impl From<$rejection> for ApiError {
+            fn from(rejection: $rejection) -> Self {
+                Self::new(rejection.status(), "invalid_request", rejection.body_text())
+            }
+        }
+    )*};
+}
+
+impl_from_rejection!(JsonRejection, MultipartRejection, PathRejection);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorInfo {
+            code: self.code.to_string(),
+            message: self.message,
+        };
+
+        (self.status, Json(body)).into_response()
+    }
+}
@@ -0,0 +1,295 @@
+/// one http client shared by everything that talks to an upstream (bancho.py api, beatmap mirrors)
+/// requests get timeouts, retries on 429/5xx and a token bucket per host,
+/// so a cold cache doesnt get us rate limited by osu.ppy.sh
+
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Response, StatusCode, Url};
+use thiserror::Error;
+
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 5;
+const DEFAULT_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_MAX_RETRIES: u32 = 3;
+const DEFAULT_RETRY_BASE_MS: u64 = 500;
+// a Retry-After of an hour isnt worth waiting for
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+// requests per second, for hosts without their own limit
+const DEFAULT_RATE_LIMIT: f64 = 10.0;
+const DEFAULT_HOST_RATE_LIMITS: &str = "osu.ppy.sh=2";
+
+#[derive(Error, Debug)]
+pub enum HttpError {
+    #[error("Request error: {0}")]
+    Request(#[from] reqwest::Error),
+    #[error("Invalid url '{0}'")]
+    InvalidUrl(String),
+    #[error("{url} responded with {status}")]
+    Status { url: String, status: StatusCode },
+}
+
+/// refills `rate` tokens per second up to `burst`, every request takes one
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    pub rate: f64,
+    pub burst: f64,
+}
+
+impl RateLimit {
+    /// `<requests per second>` or `<requests per second>:<burst>`
+    fn parse(spec: &str) -> Option<Self> {
+        let (rate, burst) = match spec.split_once(':') {
+            Some((rate, burst)) => (rate.trim().parse::<f64>().ok()?, burst.trim().parse::<f64>().ok()?),
+            None => {
+                let rate = spec.trim().parse::<f64>().ok()?;
+                (rate, rate.max(1.0))
+            },
+        };
+
+        (rate > 0.0 && burst >= 1.0).then_some(Self { rate, burst })
+    }
+}
+
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst,
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// takes a token, or returns how long to wait until there is one
+    fn take(&mut self) -> Result<(), Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.rate).min(self.limit.burst);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.limit.rate))
+        }
+    }
+}
+
+/// `Retry-After: 120` or `Retry-After: Wed, 21 Oct 2015 07:28:00 GMT`
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    // a date in the past means right away
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO))
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    pub connect_timeout: Duration,
+    /// for the whole request, body included
+    pub timeout: Duration,
+    pub max_retries: u32,
+    /// doubled on every retry
+    pub retry_base: Duration,
+    pub default_rate_limit: RateLimit,
+    pub host_rate_limits: HashMap<String, RateLimit>,
+}
+
+impl HttpConfig {
+    /// `HTTP_CONNECT_TIMEOUT_SECS`, `HTTP_TIMEOUT_SECS`, `HTTP_MAX_RETRIES`, `HTTP_RETRY_BASE_MS`,
+    /// `HTTP_RATE_LIMIT` for every host and `HTTP_RATE_LIMITS` as `host=rate[:burst],...`
+    pub fn from_env() -> Result<Self, String> {
+        fn var<T: std::str::FromStr>(name: &str, default: T) -> Result<T, String> {
+            match env::var(name) {
+                Ok(value) => value.trim().parse::<T>().map_err(|_| format!("Invalid {}: '{}'", name, value)),
+                Err(_) => Ok(default),
+            }
+        }
+
+        let default_rate_limit = match env::var("HTTP_RATE_LIMIT") {
+            Ok(spec) => RateLimit::parse(&spec).ok_or(format!("Invalid HTTP_RATE_LIMIT: '{}'", spec))?,
+            Err(_) => RateLimit { rate: DEFAULT_RATE_LIMIT, burst: DEFAULT_RATE_LIMIT },
+        };
+
+        let specs = env::var("HTTP_RATE_LIMITS").unwrap_or_else(|_| DEFAULT_HOST_RATE_LIMITS.to_string());
+        let mut host_rate_limits = HashMap::new();
+        for spec in specs.split(',').filter(|spec| !spec.trim().is_empty()) {
+            let (host, limit) = spec.split_once('=')
+                .and_then(|(host, limit)| Some((host.trim(), RateLimit::parse(limit)?)))
+                .ok_or(format!("Invalid rate limit '{}' in HTTP_RATE_LIMITS", spec))?;
+            host_rate_limits.insert(host.to_lowercase(), limit);
+        }
+
+        Ok(Self {
+            connect_timeout: Duration::from_secs(var("HTTP_CONNECT_TIMEOUT_SECS", DEFAULT_CONNECT_TIMEOUT_SECS)?),
+            timeout: Duration::from_secs(var("HTTP_TIMEOUT_SECS", DEFAULT_TIMEOUT_SECS)?),
+            max_retries: var("HTTP_MAX_RETRIES", DEFAULT_MAX_RETRIES)?,
+            retry_base: Duration::from_millis(var("HTTP_RETRY_BASE_MS", DEFAULT_RETRY_BASE_MS)?),
+            default_rate_limit,
+            host_rate_limits,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct HttpClient {
+    client: reqwest::Client,
+    config: Arc<HttpConfig>,
+    buckets: Arc<Mutex<HashMap<String, TokenBucket>>>,
+}
+
+impl HttpClient {
+    pub fn new(config: HttpConfig) -> Result<Self, HttpError> {
+        let client = reqwest::Client::builder()
+            .connect_timeout(config.connect_timeout)
+            .timeout(config.timeout)
+            .build()?;
+
+        Ok(Self {
+            client,
+            config: Arc::new(config),
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    /// waits until the host's bucket has a token
+    async fn acquire(&self, host: &str) {
+        loop {
+            let wait = {
+                let mut buckets = self.buckets.lock().unwrap();
+                let bucket = buckets.entry(host.to_string()).or_insert_with(|| {
+                    let limit = self.config.host_rate_limits.get(host)
+                        .copied()
+                        .unwrap_or(self.config.default_rate_limit);
+                    TokenBucket::new(limit)
+                });
+
+                match bucket.take() {
+                    Ok(()) => return,
+                    Err(wait) => wait,
+                }
+            };
+
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Retry-After (seconds or an http date) if the upstream sent one, otherwise exponential backoff
+    fn retry_delay(&self, attempt: u32, response: Option<&Response>) -> Duration {
+        let retry_after = response
+            .and_then(|response| response.headers().get(RETRY_AFTER))
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_retry_after);
+
+        retry_after
+            .or_else(|| self.config.retry_base.checked_mul(2u32.saturating_pow(attempt)))
+            .unwrap_or(MAX_RETRY_DELAY)
+            .min(MAX_RETRY_DELAY)
+    }
+
+    /// GETs `url`, retrying on connection errors, timeouts, 429 and 5xx
+    /// any other non-success status is returned as an error right away
+    pub async fn get(&self, url: &str) -> Result<Response, HttpError> {
+        self.get_with_timeout(url, None).await
+    }
+
+    /// like `get`, but every attempt (body included) has to finish within `timeout`
+    /// the clock only starts once the rate limiter let the attempt through,
+    /// so waiting for a token or a retry never counts against it
+    pub async fn get_with_timeout(&self, url: &str, timeout: Option<Duration>) -> Result<Response, HttpError> {
+        let host = Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_lowercase))
+            .ok_or_else(|| HttpError::InvalidUrl(url.to_string()))?;
+
+        let mut attempt = 0;
+        loop {
+            self.acquire(&host).await;
+
+            let mut request = self.client.get(url);
+            if let Some(timeout) = timeout {
+                request = request.timeout(timeout);
+            }
+
+            let delay = match request.send().await {
+                Ok(response) if response.status().is_success() => return Ok(response),
+                Ok(response) => {
+                    let status = response.status();
+                    let retryable = status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error();
+                    if !retryable || attempt >= self.config.max_retries {
+                        return Err(HttpError::Status { url: url.to_string(), status });
+                    }
+
+                    let delay = self.retry_delay(attempt, Some(&response));
+                    println!("{} responded with {}, retrying in {:?}", url, status, delay);
+                    delay
+                },
+                Err(e) => {
+                    let retryable = e.is_timeout() || e.is_connect() || e.is_request();
+                    if !retryable || attempt >= self.config.max_retries {
+                        return Err(e.into());
+                    }
+
+                    let delay = self.retry_delay(attempt, None);
+                    println!("Request to {} failed ({}), retrying in {:?}", url, e, delay);
+                    delay
+                },
+            };
+
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(spec: &str) -> Option<(f64, f64)> {
+        RateLimit::parse(spec).map(|limit| (limit.rate, limit.burst))
+    }
+
+    #[test]
+    fn parses_rate_limits() {
+        assert_eq!(parse("2"), Some((2.0, 2.0)));
+        assert_eq!(parse(" 5 : 10 "), Some((5.0, 10.0)));
+        // a burst below one request would never let anything through
+        assert_eq!(parse("0.5"), Some((0.5, 1.0)));
+    }
+
+    #[test]
+    fn rejects_invalid_rate_limits() {
+        assert_eq!(parse("0"), None);
+        assert_eq!(parse("-1"), None);
+        assert_eq!(parse("abc"), None);
+        assert_eq!(parse("2:0.5"), None);
+        assert_eq!(parse("2:"), None);
+        assert_eq!(parse(""), None);
+    }
+
+    #[test]
+    fn parses_retry_after_seconds_and_dates() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::ZERO));
+
+        let later = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(30));
+        let delay = parse_retry_after(&later).unwrap();
+        assert!(delay > Duration::from_secs(25) && delay <= Duration::from_secs(30));
+
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+}
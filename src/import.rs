@@ -0,0 +1,263 @@
+/// offline seeding of the beatmap cache from .osu files, .osz archives
+/// or an osu!stable Songs folder, for boxes that cant reach osu.ppy.sh
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::beatmap::BeatmapCache;
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportSummary {
+    pub imported: usize,
+    /// difficulties without a BeatmapID, e.g. never submitted
+    pub skipped: usize,
+    pub failed: Vec<String>,
+}
+
+/// reads `BeatmapID:` from the [Metadata] section, 0 or missing means unsubmitted
+fn parse_beatmap_id(contents: &[u8]) -> Option<u64> {
+    let contents = String::from_utf8_lossy(contents);
+    let mut in_metadata = false;
+
+    for line in contents.lines() {
+        let line = line.trim();
+
+        if line.starts_with('[') {
+            if in_metadata {
+                break;
+            }
+            in_metadata = line == "[Metadata]";
+            continue;
+        }
+
+        if in_metadata {
+            if let Some(id) = line.strip_prefix("BeatmapID:") {
+                return id.trim().parse::<u64>().ok().filter(|id| *id > 0);
+            }
+        }
+    }
+
+    None
+}
+
+fn has_extension(path: &Path, extension: &str) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case(extension))
+}
+
+/// every .osu and .osz under `path`, songs folders nest one directory per set
+/// directories that cant be read end up in `failed` instead of stopping the import
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>, failed: &mut Vec<String>) -> std::io::Result<()> {
+    if path.is_file() {
+        if has_extension(path, "osu") || has_extension(path, "osz") {
+            files.push(path.to_path_buf());
+        }
+        return Ok(());
+    }
+
+    for entry in std::fs::read_dir(path)? {
+        let (entry, file_type) = match entry.and_then(|entry| entry.file_type().map(|file_type| (entry, file_type))) {
+            Ok(entry) => entry,
+            Err(e) => {
+                failed.push(format!("{}: {}", path.display(), e));
+                continue;
+            },
+        };
+        let path = entry.path();
+
+        // symlinks arent followed at all, they can loop or point outside of IMPORT_ROOT
+        if file_type.is_symlink() {
+            continue;
+        }
+
+        if file_type.is_dir() {
+            if let Err(e) = collect_files(&path, files, failed) {
+                failed.push(format!("{}: {}", path.display(), e));
+            }
+        } else if has_extension(&path, "osu") || has_extension(&path, "osz") {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// the only directory /admin/import may read from, `IMPORT_ROOT`
+/// without one the endpoint is disabled, `ppc import <path>` works either way
+#[derive(Debug, Clone, Default)]
+pub struct ImportRoot(Option<PathBuf>);
+
+impl ImportRoot {
+    pub fn from_env() -> std::io::Result<Self> {
+        match std::env::var("IMPORT_ROOT") {
+            Ok(root) if !root.trim().is_empty() => Ok(Self(Some(std::fs::canonicalize(root.trim())?))),
+            _ => Ok(Self(None)),
+        }
+    }
+
+    /// `requested` is relative to the root, anything that ends up outside of it is refused
+    pub fn resolve(&self, requested: &str) -> Result<PathBuf, String> {
+        let Some(root) = &self.0 else {
+            return Err("Importing over http is disabled, set IMPORT_ROOT to enable it.".to_string());
+        };
+
+        let path = std::fs::canonicalize(root.join(requested))
+            .map_err(|e| format!("Cannot read '{}': {}", requested, e))?;
+        if !path.starts_with(root) {
+            return Err(format!("'{}' is outside of IMPORT_ROOT.", requested));
+        }
+
+        Ok(path)
+    }
+}
+
+/// (name, contents) of every difficulty inside an .osz
+fn read_osz(path: &Path) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+    let mut archive = zip::ZipArchive::new(file).map_err(|e| e.to_string())?;
+    let mut difficulties = Vec::new();
+
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(|e| e.to_string())?;
+        if !has_extension(Path::new(entry.name()), "osu") {
+            continue;
+        }
+
+        let name = format!("{}/{}", path.display(), entry.name());
+        let mut contents = Vec::new();
+        entry.read_to_end(&mut contents).map_err(|e| format!("{}: {}", name, e))?;
+        difficulties.push((name, contents));
+    }
+
+    Ok(difficulties)
+}
+
+async fn import_difficulty(
+    beatmap_cache: &BeatmapCache,
+    name: &str,
+    contents: &[u8],
+    summary: &mut ImportSummary,
+) {
+    let Some(beatmap_id) = parse_beatmap_id(contents) else {
+        summary.skipped += 1;
+        return;
+    };
+
+    match beatmap_cache.import_beatmap(beatmap_id, contents).await {
+        Ok(()) => summary.imported += 1,
+        Err(e) => summary.failed.push(format!("{}: {}", name, e)),
+    }
+}
+
+pub async fn import_beatmaps(beatmap_cache: &BeatmapCache, path: &Path) -> std::io::Result<ImportSummary> {
+    println!("Importing beatmaps from {}", path.display());
+
+    let root = path.to_path_buf();
+    let (files, failed) = tokio::task::spawn_blocking(move || {
+        let mut files = Vec::new();
+        let mut failed = Vec::new();
+        collect_files(&root, &mut files, &mut failed).map(|_| (files, failed))
+    })
+        .await
+        .map_err(std::io::Error::other)??;
+
+    let mut summary = ImportSummary {
+        failed,
+        ..Default::default()
+    };
+
+    for file in files {
+        if has_extension(&file, "osz") {
+            let archive = file.clone();
+            let difficulties = tokio::task::spawn_blocking(move || read_osz(&archive))
+                .await
+                .map_err(std::io::Error::other)?;
+
+            match difficulties {
+                Ok(difficulties) => {
+                    for (name, contents) in difficulties {
+                        import_difficulty(beatmap_cache, &name, &contents, &mut summary).await;
+                    }
+                },
+                Err(e) => summary.failed.push(format!("{}: {}", file.display(), e)),
+            }
+        } else {
+            let name = file.display().to_string();
+            match tokio::fs::read(&file).await {
+                Ok(contents) => import_difficulty(beatmap_cache, &name, &contents, &mut summary).await,
+                Err(e) => summary.failed.push(format!("{}: {}", name, e)),
+            }
+        }
+    }
+
+    println!(
+        "Imported {} beatmaps, skipped {}, {} failed.",
+        summary.imported, summary.skipped, summary.failed.len()
+    );
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn beatmap_id_is_read_from_metadata() {
+        let contents = b"osu file format v14\n\n[General]\nBeatmapID:1\n\n[Metadata]\nTitle:test\nBeatmapID:  129891 \nBeatmapSetID:39804\n";
+        assert_eq!(parse_beatmap_id(contents), Some(129891));
+    }
+
+    #[test]
+    fn unsubmitted_beatmaps_have_no_id() {
+        assert_eq!(parse_beatmap_id(b"[Metadata]\nBeatmapID:0\n"), None);
+        assert_eq!(parse_beatmap_id(b"[Metadata]\nBeatmapID:-1\n"), None);
+        assert_eq!(parse_beatmap_id(b"[Metadata]\nTitle:test\n[Difficulty]\nBeatmapID:5\n"), None);
+        assert_eq!(parse_beatmap_id(b""), None);
+    }
+
+    #[test]
+    fn resolve_stays_inside_the_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let root_dir = dir.path().join("root");
+        std::fs::create_dir_all(root_dir.join("songs")).unwrap();
+        std::fs::create_dir_all(dir.path().join("outside")).unwrap();
+
+        let root = ImportRoot(Some(std::fs::canonicalize(&root_dir).unwrap()));
+
+        assert!(root.resolve("songs").unwrap().ends_with("songs"));
+        assert!(root.resolve("songs/../songs").is_ok());
+        assert!(root.resolve("../outside").unwrap_err().contains("outside of IMPORT_ROOT"));
+        assert!(root.resolve("songs/../../outside").unwrap_err().contains("outside of IMPORT_ROOT"));
+    }
+
+    #[test]
+    fn resolve_is_disabled_without_a_root() {
+        assert!(ImportRoot::default().resolve("songs").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_not_collected() {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = dir.path().join("outside");
+        let songs = dir.path().join("songs");
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::create_dir_all(songs.join("set")).unwrap();
+        std::fs::write(outside.join("secret.osu"), b"").unwrap();
+        std::fs::write(songs.join("set/map.osu"), b"").unwrap();
+
+        std::os::unix::fs::symlink(outside.join("secret.osu"), songs.join("set/link.osu")).unwrap();
+        std::os::unix::fs::symlink(&outside, songs.join("outside")).unwrap();
+        std::os::unix::fs::symlink(&songs, songs.join("set/loop")).unwrap();
+
+        let mut files = Vec::new();
+        let mut failed = Vec::new();
+        collect_files(&songs, &mut files, &mut failed).unwrap();
+
+        assert_eq!(files, vec![songs.join("set/map.osu")]);
+        assert!(failed.is_empty());
+    }
+}
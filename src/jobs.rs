@@ -0,0 +1,264 @@
+/// background recalculation jobs
+/// long leaderboard-wide runs live here instead of on one http connection,
+/// so they survive the client going away and can be polled or cancelled
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use futures::StreamExt;
+use serde::Serialize;
+use tokio::task::AbortHandle;
+
+use crate::calculate::calculate::PPCalculationType;
+use crate::calculate::params::Mode;
+use crate::calculate::{stream_pp_now, CalcContext, LeaderboardOptions};
+use crate::models::{ErrorInfo, Failure, PPCalculationResult, StreamEvent};
+use crate::storage::{RunParams, RunStore};
+
+// finished jobs are dropped oldest first once there are more than this
+const MAX_FINISHED_JOBS: usize = 50;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct JobProgress {
+    /// unknown until the leaderboard has been fetched
+    pub players_total: Option<usize>,
+    pub players_done: usize,
+    pub scores_done: usize,
+    pub failures: Vec<Failure>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobInfo {
+    pub id: u64,
+    pub status: JobStatus,
+    pub progress: JobProgress,
+    /// set once the results are stored
+    pub run_id: Option<i64>,
+}
+
+struct Job {
+    status: JobStatus,
+    progress: JobProgress,
+    results: HashMap<String, Vec<PPCalculationResult>>,
+    run_params: RunParams,
+    run_id: Option<i64>,
+    abort: Option<AbortHandle>,
+}
+
+impl Job {
+    fn info(&self, id: u64) -> JobInfo {
+        JobInfo {
+            id,
+            status: self.status,
+            progress: self.progress.clone(),
+            run_id: self.run_id,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct JobStore {
+    jobs: Arc<RwLock<HashMap<u64, Job>>>,
+    next_id: Arc<AtomicU64>,
+    runs: RunStore,
+}
+
+impl JobStore {
+    pub fn new(runs: RunStore) -> Self {
+        Self {
+            jobs: Arc::new(RwLock::new(HashMap::new())),
+            next_id: Arc::new(AtomicU64::new(0)),
+            runs,
+        }
+    }
+
+    /// starts a leaderboard recalculation in the background and returns its id
+    pub fn spawn(
+        &self,
+        ctx: CalcContext,
+        mode: Mode,
+        calc_type: PPCalculationType,
+        options: LeaderboardOptions,
+        run_params: RunParams,
+    ) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+
+        {
+            let mut jobs = self.jobs.write().unwrap();
+            Self::prune_finished(&mut jobs);
+            jobs.insert(id, Job {
+                status: JobStatus::Running,
+                progress: JobProgress::default(),
+                results: HashMap::new(),
+                run_params,
+                run_id: None,
+                abort: None,
+            });
+        }
+
+        let store = self.clone();
+        let handle = tokio::spawn(async move {
+            let mut events = stream_pp_now(ctx, mode, calc_type, options);
+
+            while let Some(event) = events.next().await {
+                store.apply(id, event);
+            }
+
+            store.finish(id);
+        });
+
+        if let Some(job) = self.jobs.write().unwrap().get_mut(&id) {
+            job.abort = Some(handle.abort_handle());
+        }
+
+        println!("Started recalculation job {}", id);
+        id
+    }
+
+    fn apply(&self, id: u64, event: StreamEvent) {
+        let mut jobs = self.jobs.write().unwrap();
+        let Some(job) = jobs.get_mut(&id) else {
+            return;
+        };
+
+        // cancelled while an event was already on its way
+        if job.status != JobStatus::Running {
+            return;
+        }
+
+        match event {
+            StreamEvent::Started { players } => {
+                job.progress.players_total = Some(players);
+            },
+            StreamEvent::Player(player) => {
+                job.progress.players_done += 1;
+                job.progress.scores_done += player.results.len();
+                job.progress.failures.extend(player.failures);
+                job.results.insert(player.player_name, player.results);
+            },
+            StreamEvent::Error { player_name: Some(player_name), error } => {
+                job.progress.players_done += 1;
+                job.progress.failures.push(Failure {
+                    player_name: Some(player_name),
+                    beatmap_id: None,
+                    mods: None,
+                    error,
+                });
+            },
+            // no player means the whole run failed, e.g. the leaderboard fetch
+            StreamEvent::Error { player_name: None, error } => {
+                job.status = JobStatus::Failed;
+                job.progress.failures.push(Failure {
+                    player_name: None,
+                    beatmap_id: None,
+                    mods: None,
+                    error,
+                });
+            },
+            StreamEvent::Done => {
+                job.status = JobStatus::Completed;
+            },
+        }
+    }
+
+    fn finish(&self, id: u64) {
+        let to_save = {
+            let mut jobs = self.jobs.write().unwrap();
+            let Some(job) = jobs.get_mut(&id) else {
+                return;
+            };
+            Self::mark_finished(job);
+
+            (job.status == JobStatus::Completed)
+                .then(|| (job.run_params.clone(), job.results.clone(), job.progress.failures.clone()))
+        };
+
+        // saved without holding the lock, polls and cancels shouldnt wait on sqlite
+        if let Some((run_params, results, failures)) = to_save {
+            match tokio::task::block_in_place(|| self.runs.save_run(&run_params, &results, &failures)) {
+                Ok(run_id) => {
+                    if let Some(job) = self.jobs.write().unwrap().get_mut(&id) {
+                        job.run_id = Some(run_id);
+                    }
+                },
+                Err(e) => eprintln!("Failed to save run for job {}: {}", id, e),
+            }
+        }
+
+        println!("Recalculation job {} finished", id);
+    }
+
+    fn mark_finished(job: &mut Job) {
+        // the stream ended without saying it was done
+        if job.status == JobStatus::Running {
+            job.status = JobStatus::Failed;
+            job.progress.failures.push(Failure {
+                player_name: None,
+                beatmap_id: None,
+                mods: None,
+                error: ErrorInfo {
+                    code: "job_stopped".to_string(),
+                    message: "Job stopped unexpectedly".to_string(),
+                },
+            });
+        }
+        job.abort = None;
+    }
+
+    fn prune_finished(jobs: &mut HashMap<u64, Job>) {
+        let mut finished: Vec<u64> = jobs.iter()
+            .filter(|(_, job)| job.status != JobStatus::Running)
+            .map(|(id, _)| *id)
+            .collect();
+
+        if finished.len() <= MAX_FINISHED_JOBS {
+            return;
+        }
+
+        finished.sort_unstable();
+        for id in &finished[..finished.len() - MAX_FINISHED_JOBS] {
+            jobs.remove(id);
+        }
+    }
+
+    pub fn info(&self, id: u64) -> Option<JobInfo> {
+        self.jobs.read().unwrap().get(&id).map(|job| job.info(id))
+    }
+
+    /// `Err` holds the current status when the job hasnt completed
+    pub fn results(&self, id: u64) -> Option<Result<HashMap<String, Vec<PPCalculationResult>>, JobStatus>> {
+        let jobs = self.jobs.read().unwrap();
+        let job = jobs.get(&id)?;
+
+        if job.status == JobStatus::Completed {
+            Some(Ok(job.results.clone()))
+        } else {
+            Some(Err(job.status))
+        }
+    }
+
+    pub fn cancel(&self, id: u64) -> Option<JobInfo> {
+        let mut jobs = self.jobs.write().unwrap();
+        let job = jobs.get_mut(&id)?;
+
+        if job.status == JobStatus::Running {
+            if let Some(abort) = job.abort.take() {
+                abort.abort();
+            }
+            job.status = JobStatus::Cancelled;
+            println!("Cancelled recalculation job {}", id);
+        }
+
+        Some(job.info(id))
+    }
+}
@@ -1,74 +1,566 @@
 mod models;
 mod calculate;
 mod beatmap;
+mod jobs;
+mod storage;
+mod import;
+mod error;
+mod http;
 
 use axum::{
-    extract::{Query, State},
-    routing::get,
+    body::Body,
+    extract::{DefaultBodyLimit, FromRef, Multipart, Path, Query, State},
+    extract::rejection::{JsonRejection, MultipartRejection, PathRejection},
+    routing::{get, post},
     Router,
-    response::Json,
-    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+    http::{header, StatusCode},
 };
+use futures::StreamExt;
+use std::convert::Infallible;
 use std::error::Error;
 use std::collections::HashMap;
 
-use crate::beatmap::BeatmapCache;
-use crate::calculate::calculate_pp_now;
+use crate::beatmap::{BeatmapCache, BeatmapSource, CacheBudget, CacheStats};
+use crate::calculate::{
+    calculate_batch,
+    calculate_beatmap_difficulty,
+    calculate_pp_now,
+    calculate_score,
+    calculate_uploaded_beatmap,
+    compare_pp_now,
+    diff_runs,
+    project_totals,
+    stream_pp_now,
+    Cache,
+    CacheConfig,
+    CalcContext,
+    LeaderboardOptions,
+};
+use crate::calculate::calculate::PPCalculationType;
+use crate::calculate::engine::{registry, Branch};
+use crate::calculate::params::{Mode, NamedParam, Version};
+use crate::calculate::parsed::ParsedBeatmaps;
+use crate::jobs::{JobInfo, JobStatus, JobStore};
+use crate::storage::{RunInfo, RunParams, RunStore, StoredRun};
+use crate::import::{import_beatmaps, ImportRoot, ImportSummary};
+use crate::http::{HttpClient, HttpConfig};
+use crate::error::{ApiError, CalcError};
 
 use dotenv::dotenv;
 
+// keeps a single batch request from running forever
+const MAX_BATCH_SCORES: usize = 1000;
+// marathon maps can get big, axum only allows 2mb by default
+const MAX_UPLOAD_BYTES: usize = 16 * 1024 * 1024;
+// one request can fetch at most this many players, with at most 100 scores each
+const MAX_LEADERBOARD_LIMIT: usize = 500;
+const MAX_SCORE_LIMIT: usize = 100;
+
+#[derive(Clone)]
+struct AppState {
+    calc: CalcContext,
+    jobs: JobStore,
+    runs: RunStore,
+    import_root: ImportRoot,
+}
+
+impl FromRef<AppState> for CalcContext {
+    fn from_ref(state: &AppState) -> Self {
+        state.calc.clone()
+    }
+}
+
+impl FromRef<AppState> for BeatmapCache {
+    fn from_ref(state: &AppState) -> Self {
+        state.calc.beatmap_cache.clone()
+    }
+}
+
+impl FromRef<AppState> for JobStore {
+    fn from_ref(state: &AppState) -> Self {
+        state.jobs.clone()
+    }
+}
+
+impl FromRef<AppState> for RunStore {
+    fn from_ref(state: &AppState) -> Self {
+        state.runs.clone()
+    }
+}
+
+impl FromRef<AppState> for ImportRoot {
+    fn from_ref(state: &AppState) -> Self {
+        state.import_root.clone()
+    }
+}
+
+struct CalcParams {
+    mode: Mode,
+    version: Version,
+    rx: bool,
+    branch: Branch,
+}
+
+/// names and the old numbers both work, anything else lists what is allowed
+fn parse_param<T: NamedParam>(params: &HashMap<String, String>, key: &str, default: T) -> Result<T, ApiError> {
+    match params.get(key) {
+        Some(value) => T::parse(value).map_err(ApiError::bad_request),
+        None => Ok(default),
+    }
+}
+
+fn parse_calc_params(params: &HashMap<String, String>) -> Result<CalcParams, ApiError> {
+    let mode = parse_param(params, "mode", Mode::VanillaStd)?;
+    let version = parse_param(params, "version", Version::Vanilla)?;
+    let branch = match params.get("branch") {
+        Some(value) => registry().resolve(value).map_err(ApiError::bad_request)?,
+        None => registry().default_branch(),
+    };
+    let rx = match params.get("rx") {
+        Some(value) => value.parse::<bool>()
+            .map_err(|_| ApiError::bad_request(format!("Invalid rx '{}'. Allowed values: true, false.", value)))?,
+        None => false,
+    };
+
+    mode.game_mode().check_version(version).map_err(ApiError::bad_request)?;
+
+    Ok(CalcParams { mode, version, rx, branch })
+}
+
+fn parse_leaderboard_options(params: &HashMap<String, String>) -> Result<LeaderboardOptions, ApiError> {
+    let defaults = LeaderboardOptions::default();
+
+    // a value that doesnt parse is as invalid as one out of range, neither falls back to the default
+    let number = |name: &str, default: usize, range: std::ops::RangeInclusive<usize>, error: String| {
+        match params.get(name) {
+            Some(value) => value.parse::<usize>()
+                .ok()
+                .filter(|value| range.contains(value))
+                .ok_or_else(|| ApiError::bad_request(error)),
+            None => Ok(default),
+        }
+    };
+
+    let offset = number("offset", defaults.offset, 0..=usize::MAX, "Invalid offset. Must be 0 or more.".to_string())?;
+    let limit = number(
+        "limit", defaults.limit, 1..=MAX_LEADERBOARD_LIMIT,
+        format!("Invalid limit. Must be between 1 and {}.", MAX_LEADERBOARD_LIMIT),
+    )?;
+    let score_limit = number(
+        "score_limit", defaults.score_limit, 1..=MAX_SCORE_LIMIT,
+        format!("Invalid score_limit. Must be between 1 and {}.", MAX_SCORE_LIMIT),
+    )?;
+    let fresh = params.get("fresh")
+        .map(|m| m == "true" || m == "1")
+        .unwrap_or(defaults.fresh);
+
+    Ok(LeaderboardOptions { offset, limit, score_limit, fresh })
+}
+
+/// runs are stored with the numeric values and the leaderboard page they covered
+fn run_params(mode: Mode, version: Version, branch: Branch, rx: bool, options: &LeaderboardOptions) -> RunParams {
+    RunParams {
+        mode: mode.number(),
+        version: version.number(),
+        branch: branch.number,
+        rx,
+        engine_revision: branch.engine.revision().to_string(),
+        offset: options.offset,
+        limit: options.limit,
+        score_limit: options.score_limit,
+    }
+}
+
+/// the run id of the stored results is sent back in the `x-run-id` header
 async fn handle_pp_calculation(
+    State(ctx): State<CalcContext>,
+    State(runs): State<RunStore>,
+    Query(params): Query<HashMap<String, String>>
+) -> Result<Response, ApiError> {
+    let CalcParams { mode, version, rx, branch } = parse_calc_params(&params)?;
+    let options = parse_leaderboard_options(&params)?;
+    let run_params = run_params(mode, version, branch, rx, &options);
+
+    let calc_type = PPCalculationType::new(branch.engine, mode.game_mode(), version, rx);
+
+    let results = calculate_pp_now(&ctx, mode, calc_type, options).await?;
+
+    // a failed save shouldnt throw away the results
+    match tokio::task::block_in_place(|| runs.save_run(&run_params, &results.results, &results.failures)) {
+        Ok(run_id) => Ok((
+            [("x-run-id", run_id.to_string())],
+            Json(results),
+        ).into_response()),
+        Err(e) => {
+            eprintln!("Failed to save run: {}", e);
+            Ok(Json(results).into_response())
+        }
+    }
+}
+
+/// one ndjson line, an event that cant be serialized is sent as an error instead of an empty line
+fn stream_line(event: &models::StreamEvent) -> Vec<u8> {
+    let mut line = serde_json::to_vec(event).unwrap_or_else(|e| {
+        eprintln!("Failed to serialize stream event: {}", e);
+
+        let player_name = match event {
+            models::StreamEvent::Player(player) => Some(player.player_name.clone()),
+            _ => None,
+        };
+        let error = models::StreamEvent::Error {
+            player_name,
+            error: CalcError::Internal(format!("Failed to serialize result: {}", e)).info(),
+        };
+        // only strings, this one always serializes
+        serde_json::to_vec(&error).unwrap_or_default()
+    });
+    line.push(b'\n');
+    line
+}
+
+/// newline-delimited json, one line per player as soon as they are done
+async fn handle_pp_stream(
+    State(ctx): State<CalcContext>,
+    Query(params): Query<HashMap<String, String>>
+) -> Result<Response, ApiError> {
+    let CalcParams { mode, version, rx, branch } = parse_calc_params(&params)?;
+    let options = parse_leaderboard_options(&params)?;
+
+    let calc_type = PPCalculationType::new(branch.engine, mode.game_mode(), version, rx);
+
+    let lines = stream_pp_now(ctx, mode, calc_type, options)
+        .map(|event| Ok::<_, Infallible>(stream_line(&event)));
+
+    Ok((
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(lines),
+    ).into_response())
+}
+
+async fn handle_pp_projection(
+    State(ctx): State<CalcContext>,
+    Query(params): Query<HashMap<String, String>>
+) -> Result<Json<models::ProjectionResults>, ApiError> {
+    let CalcParams { mode, version, rx, branch } = parse_calc_params(&params)?;
+    let options = parse_leaderboard_options(&params)?;
+
+    let calc_type = PPCalculationType::new(branch.engine, mode.game_mode(), version, rx);
+
+    let results = calculate_pp_now(&ctx, mode, calc_type, options).await?;
+
+    Ok(Json(models::ProjectionResults {
+        players: project_totals(&results.results, &results.standings),
+        failures: results.failures,
+    }))
+}
+
+async fn handle_pp_comparison(
+    State(ctx): State<CalcContext>,
+    Query(params): Query<HashMap<String, String>>
+) -> Result<Json<models::ComparisonResults>, ApiError> {
+    let CalcParams { mode, version, rx, .. } = parse_calc_params(&params)?;
+    let options = parse_leaderboard_options(&params)?;
+
+    let results = compare_pp_now(&ctx, mode, version, rx, options).await?;
+
+    Ok(Json(results))
+}
+
+async fn handle_score_calculation(
     State(beatmap_cache): State<BeatmapCache>,
+    Query(params): Query<HashMap<String, String>>,
+    score: Result<Json<models::PlayerScore>, JsonRejection>,
+) -> Result<Json<models::PPCalculationResult>, ApiError> {
+    let Json(score) = score?;
+    let CalcParams { mode, version, rx, branch } = parse_calc_params(&params)?;
+    let player_name = params.get("player")
+        .cloned()
+        .unwrap_or_else(|| "unknown".to_string());
+
+    let calc_type = PPCalculationType::new(branch.engine, mode.game_mode(), version, rx);
+
+    let result = calculate_score(
+        &beatmap_cache,
+        &score,
+        &player_name,
+        calc_type,
+    ).await?;
+
+    Ok(Json(result))
+}
+
+async fn handle_batch_calculation(
+    State(beatmap_cache): State<BeatmapCache>,
+    Query(params): Query<HashMap<String, String>>,
+    scores: Result<Json<Vec<models::BatchScore>>, JsonRejection>,
+) -> Result<Json<Vec<models::BatchScoreResult>>, ApiError> {
+    let Json(scores) = scores?;
+    let CalcParams { mode, version, rx, branch } = parse_calc_params(&params)?;
+
+    if scores.len() > MAX_BATCH_SCORES {
+        return Err(ApiError::bad_request(format!("Too many scores. At most {} per batch.", MAX_BATCH_SCORES)));
+    }
+
+    let calc_type = PPCalculationType::new(branch.engine, mode.game_mode(), version, rx);
+
+    // failed scores come back as error entries, the batch itself doesnt fail
+    let results = calculate_batch(
+        &beatmap_cache,
+        scores,
+        calc_type,
+    ).await;
+
+    Ok(Json(results))
+}
+
+/// star rating only, `beatmap_id` is required and `md5` makes sure it is the right version
+async fn handle_difficulty_calculation(
+    State(beatmap_cache): State<BeatmapCache>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Result<Json<models::DifficultyResult>, ApiError> {
+    let CalcParams { mode, version, rx, branch } = parse_calc_params(&params)?;
+    let beatmap_id = params.get("beatmap_id")
+        .ok_or_else(|| ApiError::bad_request("Missing beatmap_id."))?
+        .parse::<u64>()
+        .map_err(|_| ApiError::bad_request("Invalid beatmap_id."))?;
+    let mods = match params.get("mods") {
+        Some(value) => value.parse::<u32>().map_err(|_| ApiError::bad_request("Invalid mods."))?,
+        None => 0,
+    };
+    let md5 = params.get("md5").map(String::as_str).unwrap_or_default();
+
+    let calc_type = PPCalculationType::new(branch.engine, mode.game_mode(), version, rx);
+
+    let stars = calculate_beatmap_difficulty(&beatmap_cache, beatmap_id, md5, mods, calc_type).await?;
+
+    Ok(Json(models::DifficultyResult {
+        beatmap_id,
+        mods,
+        stars,
+        version: version.number(),
+        engine_revision: calc_type.engine_revision().to_string(),
+    }))
+}
+
+fn parse_upload_field<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, ApiError> {
+    value.trim().parse::<T>().map_err(|_| ApiError::bad_request(
+        format!("Invalid value for '{}'.", name)
+    ))
+}
+
+async fn handle_upload_calculation(
+    multipart: Result<Multipart, MultipartRejection>,
+) -> Result<Json<HashMap<String, Vec<models::PPCalculationResult>>>, ApiError> {
+    let mut multipart = multipart?;
+    let mut contents = None;
+    let mut mods = 0;
+    let mut acc = 100.0;
+    let mut combo = None;
+    let mut misses = 0;
+    let mut rx = false;
+    let mut hits: Option<models::UploadHitCounts> = None;
+
+    while let Some(field) = multipart.next_field().await
+        .map_err(|e| ApiError::bad_request(e.to_string()))?
+    {
+        let name = field.name().unwrap_or_default().to_string();
+
+        if name == "file" {
+            let bytes = field.bytes().await
+                .map_err(|e| ApiError::bad_request(e.to_string()))?;
+            contents = Some(bytes);
+            continue;
+        }
+
+        let value = field.text().await
+            .map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+        match name.as_str() {
+            "mods" => mods = parse_upload_field(&name, &value)?,
+            "acc" => acc = parse_upload_field(&name, &value)?,
+            "combo" => combo = Some(parse_upload_field(&name, &value)?),
+            "misses" => misses = parse_upload_field(&name, &value)?,
+            "rx" => rx = parse_upload_field(&name, &value)?,
+            "n300" => hits.get_or_insert_with(Default::default).n300 = parse_upload_field(&name, &value)?,
+            "n100" => hits.get_or_insert_with(Default::default).n100 = parse_upload_field(&name, &value)?,
+            "n50" => hits.get_or_insert_with(Default::default).n50 = parse_upload_field(&name, &value)?,
+            "ngeki" => hits.get_or_insert_with(Default::default).ngeki = parse_upload_field(&name, &value)?,
+            "nkatu" => hits.get_or_insert_with(Default::default).nkatu = parse_upload_field(&name, &value)?,
+            _ => {}
+        }
+    }
+
+    let contents = contents.ok_or_else(|| ApiError::bad_request("Missing 'file' field."))?;
+    let combo = combo.ok_or_else(|| ApiError::bad_request("Missing 'combo' field."))?;
+
+    if !(0.0..=100.0).contains(&acc) {
+        return Err(ApiError::bad_request("Invalid acc. Must be between 0 and 100."));
+    }
+
+    let params = models::UploadScoreParams { mods, acc, combo, misses, rx, hits };
+
+    let results = calculate_uploaded_beatmap(&contents, params).await?;
+
+    Ok(Json(results))
+}
+
+/// same parameters as /calculate_pp, but runs in the background
+async fn handle_job_creation(
+    State(ctx): State<CalcContext>,
+    State(jobs): State<JobStore>,
     Query(params): Query<HashMap<String, String>>
-) -> Result<Json<HashMap<String, Vec<models::PPCalculationResult>>>, (StatusCode, String)> {
-    let mode = params.get("mode")
-        .and_then(|m| m.parse::<u8>().ok())
-        .unwrap_or(0);
-    let version = params.get("version")
-        .and_then(|m| m.parse::<u8>().ok())
-        .unwrap_or(0);
-    let rx = params.get("rx")
-        .and_then(|m| m.parse::<bool>().ok())
-        .unwrap_or(false);
-    let branch = params.get("branch")
-        .and_then(|m| m.parse::<u8>().ok())
-        .unwrap_or( 0);
-
-    if mode > 8 {
-        return Err((
-            StatusCode::BAD_REQUEST, 
-            "Invalid mode. Must be between 0 and 8.".to_string()
-        ));
-    }
-
-    if version > 2 {
-        return Err((
-            StatusCode::BAD_REQUEST, 
-            "Invalid version. Must be between 0 and 2.".to_string()
-        ));
-    }
-
-    if branch > 5 {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            "Invalid branch. Must be between 0 and 5".to_string()
-        ))
-    }
-
-    match calculate_pp_now(
-        mode, 
-        &beatmap_cache, 
-        version, 
-        rx,
-        branch,
-    ).await {
-        Ok(results) => Ok(Json(results)),
+) -> Result<(StatusCode, Json<JobInfo>), ApiError> {
+    let CalcParams { mode, version, rx, branch } = parse_calc_params(&params)?;
+    let options = parse_leaderboard_options(&params)?;
+    let run_params = run_params(mode, version, branch, rx, &options);
+
+    let calc_type = PPCalculationType::new(branch.engine, mode.game_mode(), version, rx);
+
+    let id = jobs.spawn(ctx, mode, calc_type, options, run_params);
+    let info = jobs.info(id).ok_or_else(|| ApiError::internal("Failed to create job"))?;
+
+    Ok((StatusCode::ACCEPTED, Json(info)))
+}
+
+fn job_not_found(id: u64) -> ApiError {
+    ApiError::not_found(format!("Job {} not found.", id))
+}
+
+async fn handle_job_status(
+    State(jobs): State<JobStore>,
+    id: Result<Path<u64>, PathRejection>,
+) -> Result<Json<JobInfo>, ApiError> {
+    let Path(id) = id?;
+    jobs.info(id)
+        .map(Json)
+        .ok_or_else(|| job_not_found(id))
+}
+
+async fn handle_job_results(
+    State(jobs): State<JobStore>,
+    id: Result<Path<u64>, PathRejection>,
+) -> Result<Json<HashMap<String, Vec<models::PPCalculationResult>>>, ApiError> {
+    let Path(id) = id?;
+    match jobs.results(id) {
+        Some(Ok(results)) => Ok(Json(results)),
+        Some(Err(JobStatus::Running)) => Err(ApiError::conflict(format!("Job {} is still running.", id))),
+        Some(Err(status)) => Err(ApiError::conflict(format!("Job {} did not complete ({:?}).", id, status))),
+        None => Err(job_not_found(id)),
+    }
+}
+
+async fn handle_job_cancel(
+    State(jobs): State<JobStore>,
+    id: Result<Path<u64>, PathRejection>,
+) -> Result<Json<JobInfo>, ApiError> {
+    let Path(id) = id?;
+    jobs.cancel(id)
+        .map(Json)
+        .ok_or_else(|| job_not_found(id))
+}
+
+async fn handle_run_list(
+    State(runs): State<RunStore>,
+    Query(params): Query<HashMap<String, String>>
+) -> Result<Json<Vec<RunInfo>>, ApiError> {
+    let limit = params.get("limit")
+        .and_then(|m| m.parse::<usize>().ok())
+        .unwrap_or(50);
+
+    match tokio::task::block_in_place(|| runs.list_runs(limit)) {
+        Ok(runs) => Ok(Json(runs)),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            Err(ApiError::internal("Failed to list runs"))
+        }
+    }
+}
+
+async fn handle_run_fetch(
+    State(runs): State<RunStore>,
+    id: Result<Path<i64>, PathRejection>,
+) -> Result<Json<StoredRun>, ApiError> {
+    let Path(id) = id?;
+    match tokio::task::block_in_place(|| runs.get_run(id)) {
+        Ok(Some(run)) => Ok(Json(run)),
+        Ok(None) => Err(ApiError::not_found(format!("Run {} not found.", id))),
         Err(e) => {
             eprintln!("Error: {}", e);
-            Err((
-                StatusCode::INTERNAL_SERVER_ERROR, 
-                "Failed to calculate PP".to_string()
-            ))
+            Err(ApiError::internal("Failed to fetch run"))
+        }
+    }
+}
+
+async fn handle_run_diff(
+    State(runs): State<RunStore>,
+    ids: Result<Path<(i64, i64)>, PathRejection>,
+) -> Result<Json<models::RunDiff>, ApiError> {
+    let Path((run_a, run_b)) = ids?;
+    let fetch = |id: i64| match tokio::task::block_in_place(|| runs.get_run(id)) {
+        Ok(Some(run)) => Ok(run),
+        Ok(None) => Err(ApiError::not_found(format!("Run {} not found.", id))),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            Err(ApiError::internal("Failed to fetch run"))
+        }
+    };
+
+    let a = fetch(run_a)?;
+    let b = fetch(run_b)?;
+
+    Ok(Json(diff_runs(run_a, &a.results, &a.failures, run_b, &b.results, &b.failures)))
+}
+
+#[derive(serde::Serialize)]
+struct BeatmapCacheInfo {
+    beatmap_id: u64,
+    cached: bool,
+    /// unknown for maps cached before sources were recorded
+    source: Option<String>,
+}
+
+async fn handle_branch_list() -> Json<Vec<models::BranchInfo>> {
+    Json(registry().info())
+}
+
+async fn handle_beatmap_info(
+    State(beatmap_cache): State<BeatmapCache>,
+    beatmap_id: Result<Path<u64>, PathRejection>,
+) -> Result<Json<BeatmapCacheInfo>, ApiError> {
+    let Path(beatmap_id) = beatmap_id?;
+    Ok(Json(BeatmapCacheInfo {
+        beatmap_id,
+        cached: beatmap_cache.get_beatmap_path(beatmap_id).exists(),
+        source: beatmap_cache.served_by(beatmap_id),
+    }))
+}
+
+async fn handle_cache_stats(
+    State(beatmap_cache): State<BeatmapCache>,
+) -> Json<CacheStats> {
+    Json(beatmap_cache.stats())
+}
+
+#[derive(serde::Deserialize)]
+struct ImportRequest {
+    /// a directory, .osu file, .osz archive or osu! Songs folder inside IMPORT_ROOT
+    path: String,
+}
+
+async fn handle_beatmap_import(
+    State(beatmap_cache): State<BeatmapCache>,
+    State(import_root): State<ImportRoot>,
+    request: Result<Json<ImportRequest>, JsonRejection>,
+) -> Result<Json<ImportSummary>, ApiError> {
+    let Json(request) = request?;
+    let path = import_root.resolve(&request.path).map_err(ApiError::forbidden)?;
+
+    match import_beatmaps(&beatmap_cache, &path).await {
+        Ok(summary) => Ok(Json(summary)),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            Err(ApiError::bad_request(format!("Failed to import from '{}': {}", request.path, e)))
         }
     }
 }
@@ -76,17 +568,100 @@ async fn handle_pp_calculation(
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     dotenv().ok();
-    let beatmap_cache = BeatmapCache::new();
+    let sources = BeatmapSource::from_env()?;
+    println!(
+        "Beatmap sources: {}",
+        sources.iter().map(|s| s.name.as_str()).collect::<Vec<_>>().join(", ")
+    );
+    let http = HttpClient::new(HttpConfig::from_env()?)?;
+    let beatmap_cache = BeatmapCache::new(
+        sources,
+        http.clone(),
+        CacheBudget::from_env(),
+        ParsedBeatmaps::from_env(),
+    );
 
     beatmap_cache.ensure_cache_exists().await?;
 
+    // `ppc import <path>` seeds the cache and exits, for machines without network access
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("import") {
+        let path = args.get(2).ok_or("Usage: ppc import <path>")?;
+        let summary = import_beatmaps(&beatmap_cache, std::path::Path::new(path)).await?;
+        for failure in &summary.failed {
+            eprintln!("Failed: {}", failure);
+        }
+        return Ok(());
+    }
+
+    let runs = RunStore::open()?;
+
+    let state = AppState {
+        calc: CalcContext {
+            http,
+            cache: Cache::new(CacheConfig::from_env()),
+            beatmap_cache,
+        },
+        jobs: JobStore::new(runs.clone()),
+        runs,
+        import_root: ImportRoot::from_env()?,
+    };
+
     let app = Router::new()
         .route("/calculate_pp", get(handle_pp_calculation))
-        .with_state(beatmap_cache);
+        .route("/calculate_pp/stream", get(handle_pp_stream))
+        .route("/projected_pp", get(handle_pp_projection))
+        .route("/compare_pp", get(handle_pp_comparison))
+        .route("/calculate_score", post(handle_score_calculation))
+        .route("/calculate_batch", post(handle_batch_calculation))
+        .route("/difficulty", get(handle_difficulty_calculation))
+        .route(
+            "/calculate_upload",
+            post(handle_upload_calculation).layer(DefaultBodyLimit::max(MAX_UPLOAD_BYTES))
+        )
+        .route("/jobs", post(handle_job_creation))
+        .route("/jobs/:id", get(handle_job_status).delete(handle_job_cancel))
+        .route("/jobs/:id/results", get(handle_job_results))
+        .route("/branches", get(handle_branch_list))
+        .route("/runs", get(handle_run_list))
+        .route("/runs/:id", get(handle_run_fetch))
+        .route("/runs/:id/diff/:other", get(handle_run_diff))
+        .route("/admin/cache", get(handle_cache_stats))
+        .route("/admin/beatmaps/:id", get(handle_beatmap_info))
+        .route("/admin/import", post(handle_beatmap_import))
+        .with_state(state);
 
     let listener = tokio::net::TcpListener::bind("127.0.0.1:8670").await?;
     println!("Server running on http://127.0.0.1:8670");
     axum::serve(listener, app).await?;
 
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(query: &[(&str, &str)]) -> Result<LeaderboardOptions, ApiError> {
+        let params = query.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        parse_leaderboard_options(&params)
+    }
+
+    #[test]
+    fn leaderboard_options_default_when_missing() {
+        let defaults = LeaderboardOptions::default();
+        let parsed = options(&[]).unwrap();
+
+        assert_eq!(parsed.offset, defaults.offset);
+        assert_eq!(parsed.limit, defaults.limit);
+        assert_eq!(parsed.score_limit, defaults.score_limit);
+    }
+
+    #[test]
+    fn invalid_leaderboard_options_are_rejected() {
+        for query in [("limit", "abc"), ("limit", "0"), ("offset", "-1"), ("score_limit", "x")] {
+            let error = options(&[query]).unwrap_err();
+            assert_eq!(error.status, StatusCode::BAD_REQUEST, "{:?}", query);
+            assert_eq!(error.code, "invalid_request");
+        }
+    }
+}
@@ -1,6 +1,8 @@
+use std::collections::{BTreeMap, HashMap};
+
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct PlayerScore {
     pub score: u64,
     pub pp: f64,
@@ -11,6 +13,11 @@ pub struct PlayerScore {
     pub n100: usize,
     pub n50: usize,
     pub nmiss: usize,
+    /// only used by taiko, catch and mania, std scores can leave them out
+    #[serde(default)]
+    pub ngeki: usize,
+    #[serde(default)]
+    pub nkatu: usize,
 
     pub aim_value: usize,
     pub ar_value: f64,
@@ -21,7 +28,7 @@ pub struct PlayerScore {
     pub beatmap: BeatmapInfo,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct BeatmapInfo {
     pub id: u64,
     pub md5: String,
@@ -46,7 +53,7 @@ pub struct ScoresResponse {
     pub scores: Vec<PlayerScore>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PPCalculationResult {
     pub stars: f64,
     pub beatmap_id: u64,
@@ -55,4 +62,235 @@ pub struct PPCalculationResult {
     pub difference: f64,
     pub mods: u32,
     pub version: u8,
-}
\ No newline at end of file
+    /// git revision of the engine that calculated it, see /branches
+    #[serde(default)]
+    pub engine_revision: String,
+}
+
+/// star rating of a map on one branch, see /difficulty
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DifficultyResult {
+    pub beatmap_id: u64,
+    pub mods: u32,
+    pub stars: f64,
+    pub version: u8,
+    pub engine_revision: String,
+}
+
+/// a score submitted to the batch endpoint, optionally tagged with who set it
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BatchScore {
+    #[serde(default)]
+    pub player_name: Option<String>,
+    #[serde(default)]
+    pub player_id: Option<u64>,
+
+    #[serde(flatten)]
+    pub score: PlayerScore,
+}
+
+/// either the result fields or `error` are set, a failed score doesnt fail the batch
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BatchScoreResult {
+    pub player_name: Option<String>,
+    pub player_id: Option<u64>,
+
+    #[serde(flatten)]
+    pub result: Option<PPCalculationResult>,
+    pub error: Option<ErrorInfo>,
+}
+
+/// `code` is stable and meant for machines, `message` is for humans
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorInfo {
+    pub code: String,
+    pub message: String,
+}
+
+/// a score or player that couldnt be calculated, the rest of the response is still valid
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Failure {
+    pub player_name: Option<String>,
+    /// not set when the player's scores couldnt be fetched at all
+    pub beatmap_id: Option<u64>,
+    pub mods: Option<u32>,
+
+    #[serde(flatten)]
+    pub error: ErrorInfo,
+}
+
+/// a player's real total and position when the leaderboard was fetched
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct LeaderboardStanding {
+    pub pp: f64,
+    pub rank: usize,
+}
+
+/// recalculated scores per player, plus everything that failed along the way
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LeaderboardResults {
+    pub results: HashMap<String, Vec<PPCalculationResult>>,
+    pub failures: Vec<Failure>,
+    /// only used for /projected_pp, the leaderboard itself isnt part of the response
+    #[serde(skip)]
+    pub standings: HashMap<String, LeaderboardStanding>,
+}
+
+/// hit counts of an uploaded score, taiko, catch and mania cant be guessed from acc
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UploadHitCounts {
+    pub n300: usize,
+    pub n100: usize,
+    pub n50: usize,
+    pub ngeki: usize,
+    pub nkatu: usize,
+}
+
+/// score parameters sent along with an uploaded .osu file
+#[derive(Debug, Clone)]
+pub struct UploadScoreParams {
+    pub mods: u32,
+    pub acc: f64,
+    pub combo: usize,
+    pub misses: usize,
+    pub rx: bool,
+    /// std maps fall back to hit counts from `acc` without them
+    pub hits: Option<UploadHitCounts>,
+}
+
+/// one score ran through every branch
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScoreComparison {
+    pub beatmap_id: u64,
+    pub mods: u32,
+    pub version: u8,
+    pub original_pp: f64,
+    /// branch name -> recalculated pp
+    pub pp: BTreeMap<String, f64>,
+    /// branch name -> stars
+    pub stars: BTreeMap<String, f64>,
+    /// "a-b" -> pp on branch a minus pp on branch b, for every pair
+    pub differences: BTreeMap<String, f64>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ComparisonResults {
+    pub results: HashMap<String, Vec<ScoreComparison>>,
+    /// a score that failed on any branch is left out of `results`
+    pub failures: Vec<Failure>,
+    /// branch -> branch it gives the same pp as in this mode, those arent calculated twice
+    pub same_as: BTreeMap<String, String>,
+}
+
+/// a player's total before and after a recalculation
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlayerProjection {
+    pub player_name: String,
+    pub old_total: f64,
+    pub new_total: f64,
+    pub difference: f64,
+    pub old_rank: usize,
+    pub new_rank: usize,
+    pub rank_change: i64,
+}
+
+/// failed scores are left out of the totals, so they are listed next to them
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProjectionResults {
+    pub players: Vec<PlayerProjection>,
+    pub failures: Vec<Failure>,
+}
+
+/// all recalculated scores of one player
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlayerResults {
+    pub player_name: String,
+    pub results: Vec<PPCalculationResult>,
+    pub failures: Vec<Failure>,
+}
+
+/// one line of the streamed /calculate_pp output
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamEvent {
+    /// the leaderboard was fetched, this many players will follow
+    Started {
+        players: usize,
+    },
+    Player(PlayerResults),
+    /// no player means the whole run failed, e.g. the leaderboard fetch
+    Error {
+        player_name: Option<String>,
+
+        #[serde(flatten)]
+        error: ErrorInfo,
+    },
+    Done,
+}
+
+/// a stored score that only exists in one of two diffed runs
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunScore {
+    pub player_name: String,
+
+    #[serde(flatten)]
+    pub result: PPCalculationResult,
+}
+
+/// a score present in both runs, `result` is from run b
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScoreDelta {
+    pub player_name: String,
+
+    #[serde(flatten)]
+    pub result: PPCalculationResult,
+    /// recalculated pp in run b minus run a
+    pub delta: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlayerDelta {
+    pub player_name: String,
+    /// weighted totals like in /projected_pp
+    pub total_a: f64,
+    pub total_b: f64,
+    pub delta: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BeatmapDelta {
+    pub beatmap_id: u64,
+    pub scores: usize,
+    pub total_delta: f64,
+    pub average_delta: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RunDiff {
+    pub run_a: i64,
+    pub run_b: i64,
+    pub scores: Vec<ScoreDelta>,
+    pub players: Vec<PlayerDelta>,
+    pub beatmaps: Vec<BeatmapDelta>,
+    /// only in run b
+    pub appeared: Vec<RunScore>,
+    /// only in run a
+    pub disappeared: Vec<RunScore>,
+    /// only in one run because they failed in the other, not counted as a change
+    pub failed: Vec<RunScore>,
+}
+
+/// one calculator this server provides, see /branches
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BranchInfo {
+    pub name: String,
+    /// what older clients send in `branch=` instead of the name
+    pub number: u8,
+    pub description: String,
+    /// names of the versions it can calculate, e.g. "scorev2"
+    pub versions: Vec<String>,
+    /// whether it reads the cheat values (aim_value, ar_value, ...) of a score
+    pub cheats: bool,
+    /// git revision the crate is pinned to in Cargo.toml
+    pub revision: String,
+}
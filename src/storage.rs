@@ -0,0 +1,312 @@
+/// keeps every recalculation run around in sqlite
+/// so a run can be cited later instead of re-running it and getting different numbers
+
+use std::collections::HashMap;
+use std::env;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::models::{ErrorInfo, Failure, PPCalculationResult};
+
+#[derive(Error, Debug)]
+pub enum StorageError {
+    #[error("IO error: {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] rusqlite::Error),
+}
+
+/// what a run was calculated with
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunParams {
+    pub mode: u8,
+    pub version: u8,
+    pub branch: u8,
+    pub rx: bool,
+    pub engine_revision: String,
+    /// the leaderboard page and scores per player
+    pub offset: usize,
+    pub limit: usize,
+    pub score_limit: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunInfo {
+    pub id: i64,
+    #[serde(flatten)]
+    pub params: RunParams,
+    /// unix seconds
+    pub created_at: i64,
+    pub scores: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StoredRun {
+    #[serde(flatten)]
+    pub info: RunInfo,
+    pub results: HashMap<String, Vec<PPCalculationResult>>,
+    /// scores and players that couldnt be calculated in this run
+    pub failures: Vec<Failure>,
+}
+
+#[derive(Clone)]
+pub struct RunStore {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl RunStore {
+    pub fn open() -> Result<Self, StorageError> {
+        let path = env::var("DATABASE_PATH").unwrap_or_else(|_| ".data/ppc.db".to_string());
+
+        if let Some(parent) = Path::new(&path).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let conn = Connection::open(&path)?;
+        conn.execute_batch(
+            "PRAGMA foreign_keys = ON;
+
+            CREATE TABLE IF NOT EXISTS runs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                mode INTEGER NOT NULL,
+                version INTEGER NOT NULL,
+                branch INTEGER NOT NULL,
+                rx INTEGER NOT NULL,
+                engine_revision TEXT NOT NULL,
+                created_at INTEGER NOT NULL,
+                leaderboard_offset INTEGER NOT NULL,
+                leaderboard_limit INTEGER NOT NULL,
+                score_limit INTEGER NOT NULL
+            );
+
+            CREATE TABLE IF NOT EXISTS results (
+                run_id INTEGER NOT NULL REFERENCES runs(id) ON DELETE CASCADE,
+                player_name TEXT NOT NULL,
+                position INTEGER NOT NULL,
+                beatmap_id INTEGER NOT NULL,
+                mods INTEGER NOT NULL,
+                version INTEGER NOT NULL,
+                stars REAL NOT NULL,
+                original_pp REAL NOT NULL,
+                recalculated_pp REAL NOT NULL,
+                difference REAL NOT NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS results_run_id ON results(run_id);
+
+            CREATE TABLE IF NOT EXISTS failures (
+                run_id INTEGER NOT NULL REFERENCES runs(id) ON DELETE CASCADE,
+                player_name TEXT NOT NULL,
+                beatmap_id INTEGER,
+                mods INTEGER,
+                code TEXT NOT NULL,
+                message TEXT NOT NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS failures_run_id ON failures(run_id);"
+        )?;
+
+        println!("Opened run database at {}", path);
+        Ok(Self { conn: Arc::new(Mutex::new(conn)) })
+    }
+
+    /// stores a run with all of its results and failures, returns the new run id
+    /// failures without a player failed the whole run and arent stored
+    pub fn save_run(
+        &self,
+        params: &RunParams,
+        results: &HashMap<String, Vec<PPCalculationResult>>,
+        failures: &[Failure],
+    ) -> Result<i64, StorageError> {
+        let created_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        tx.execute(
+            "INSERT INTO runs (
+                mode, version, branch, rx, engine_revision, created_at,
+                leaderboard_offset, leaderboard_limit, score_limit
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                params.mode,
+                params.version,
+                params.branch,
+                params.rx,
+                params.engine_revision,
+                created_at,
+                params.offset,
+                params.limit,
+                params.score_limit,
+            ],
+        )?;
+        let run_id = tx.last_insert_rowid();
+
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO results (
+                    run_id, player_name, position, beatmap_id, mods, version,
+                    stars, original_pp, recalculated_pp, difference
+                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)"
+            )?;
+
+            for (player_name, player_results) in results {
+                for (position, result) in player_results.iter().enumerate() {
+                    insert.execute(params![
+                        run_id,
+                        player_name,
+                        position as i64,
+                        result.beatmap_id as i64,
+                        result.mods,
+                        result.version,
+                        result.stars,
+                        result.original_pp,
+                        result.recalculated_pp,
+                        result.difference,
+                    ])?;
+                }
+            }
+
+            let mut insert = tx.prepare(
+                "INSERT INTO failures (run_id, player_name, beatmap_id, mods, code, message)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
+            )?;
+
+            for failure in failures {
+                let Some(player_name) = &failure.player_name else {
+                    continue;
+                };
+                insert.execute(params![
+                    run_id,
+                    player_name,
+                    failure.beatmap_id.map(|id| id as i64),
+                    failure.mods,
+                    failure.error.code,
+                    failure.error.message,
+                ])?;
+            }
+        }
+
+        tx.commit()?;
+
+        println!("Saved run {}", run_id);
+        Ok(run_id)
+    }
+
+    /// newest first
+    pub fn list_runs(&self, limit: usize) -> Result<Vec<RunInfo>, StorageError> {
+        let conn = self.conn.lock().unwrap();
+        let mut query = conn.prepare(
+            "SELECT r.id, r.mode, r.version, r.branch, r.rx, r.engine_revision, r.created_at,
+                (SELECT COUNT(*) FROM results WHERE run_id = r.id),
+                r.leaderboard_offset, r.leaderboard_limit, r.score_limit
+            FROM runs r
+            ORDER BY r.id DESC
+            LIMIT ?1"
+        )?;
+
+        let runs = query
+            .query_map(params![limit as i64], run_info_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(runs)
+    }
+
+    pub fn get_run(&self, id: i64) -> Result<Option<StoredRun>, StorageError> {
+        let conn = self.conn.lock().unwrap();
+
+        let info = conn.query_row(
+            "SELECT r.id, r.mode, r.version, r.branch, r.rx, r.engine_revision, r.created_at,
+                (SELECT COUNT(*) FROM results WHERE run_id = r.id),
+                r.leaderboard_offset, r.leaderboard_limit, r.score_limit
+            FROM runs r
+            WHERE r.id = ?1",
+            params![id],
+            run_info_from_row,
+        ).optional()?;
+
+        let Some(info) = info else {
+            return Ok(None);
+        };
+
+        let mut query = conn.prepare(
+            "SELECT player_name, beatmap_id, mods, version, stars, original_pp, recalculated_pp, difference
+            FROM results
+            WHERE run_id = ?1
+            ORDER BY player_name, position"
+        )?;
+
+        // results are stored per run, so they all share the run's revision
+        let engine_revision = info.params.engine_revision.clone();
+        let rows = query.query_map(params![id], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                PPCalculationResult {
+                    beatmap_id: row.get::<_, i64>(1)? as u64,
+                    mods: row.get(2)?,
+                    version: row.get(3)?,
+                    stars: row.get(4)?,
+                    original_pp: row.get(5)?,
+                    recalculated_pp: row.get(6)?,
+                    difference: row.get(7)?,
+                    engine_revision: engine_revision.clone(),
+                },
+            ))
+        })?;
+
+        let mut results: HashMap<String, Vec<PPCalculationResult>> = HashMap::new();
+        for row in rows {
+            let (player_name, result) = row?;
+            results.entry(player_name).or_default().push(result);
+        }
+
+        let mut query = conn.prepare(
+            "SELECT player_name, beatmap_id, mods, code, message
+            FROM failures
+            WHERE run_id = ?1
+            ORDER BY player_name, beatmap_id"
+        )?;
+
+        let failures = query
+            .query_map(params![id], |row| {
+                Ok(Failure {
+                    player_name: Some(row.get(0)?),
+                    beatmap_id: row.get::<_, Option<i64>>(1)?.map(|id| id as u64),
+                    mods: row.get(2)?,
+                    error: ErrorInfo {
+                        code: row.get(3)?,
+                        message: row.get(4)?,
+                    },
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Some(StoredRun { info, results, failures }))
+    }
+}
+
+fn run_info_from_row(row: &rusqlite::Row) -> rusqlite::Result<RunInfo> {
+    Ok(RunInfo {
+        id: row.get(0)?,
+        params: RunParams {
+            mode: row.get(1)?,
+            version: row.get(2)?,
+            branch: row.get(3)?,
+            rx: row.get(4)?,
+            engine_revision: row.get(5)?,
+            offset: row.get(8)?,
+            limit: row.get(9)?,
+            score_limit: row.get(10)?,
+        },
+        created_at: row.get(6)?,
+        scores: row.get::<_, i64>(7)? as usize,
+    })
+}